@@ -46,6 +46,7 @@ fn exec_program(prog: &mut [i32]) -> i32 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use intcode::fuzz;
 
     #[test]
     fn example_inputs_part1() {
@@ -69,4 +70,19 @@ mod tests {
         assert_eq!(res4, 30);
         assert_eq!(prog4, vec![30, 1, 1, 4, 2, 5, 6, 0, 99, 0, 0, 0]);
     }
+
+    fn day02_engine(prog: &[i64]) -> Vec<i64> {
+        let mut prog: Vec<_> = prog.iter().map(|&v| v as i32).collect();
+        exec_program(&mut prog);
+        prog.iter().map(|&v| i64::from(v)).collect()
+    }
+
+    #[test]
+    fn differential_fuzzing() {
+        let cfg        = fuzz::GenConfig::default();
+        let mismatches = fuzz::differential(day02_engine, fuzz::intcode_engine, &cfg, 2019, 500);
+        if let Some(m) = mismatches.first() {
+            panic!("{} mismatches, first one:\n{}", mismatches.len(), m);
+        }
+    }
 }
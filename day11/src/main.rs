@@ -3,8 +3,8 @@ use std::collections::VecDeque;
 use std::error::Error;
 use std::fs;
 
+use intcode::stream::Flow;
 use intcode::IntcodeProg;
-use intcode::ProgramStatus as IPS;
 
 #[derive(Copy, Clone)]
 enum Dir {
@@ -60,31 +60,21 @@ fn paint_panels(prog: &[i64], start_color: i64) -> HashMap<(i64, i64), i64> {
     let mut dir    = Dir::Up;
     let mut xpos   = 0;
     let mut ypos   = 0;
-    let mut input  = VecDeque::new();
-    let mut output = VecDeque::new();
-    input.push_back(start_color);
 
-    loop {
-        let result = prog.exec_instr(&mut input, &mut output);
-        if output.len() == 2 {
-            let color  = output.pop_front().unwrap();
-            let turn   = output.pop_front().unwrap();
-            panels.insert((xpos, ypos), color);
-            dir        = new_dir(dir, turn);
-            let (x, y) = new_pos(xpos, ypos, dir);
-            xpos       = x;
-            ypos       = y;
-            let floor_color = match panels.get(&(xpos, ypos)) {
-                Some(&color) => color,
-                None         => 0,
-            };
-            input.push_back(floor_color);
-        }
-        if result == IPS::Finished {
-            assert_eq!(output.len(), 0);
-            break;
-        }
-    }
+    let mut paint = |[color, turn]: [i64; 2], input: &mut VecDeque<i64>| {
+        panels.insert((xpos, ypos), color);
+        dir        = new_dir(dir, turn);
+        let (x, y) = new_pos(xpos, ypos, dir);
+        xpos       = x;
+        ypos       = y;
+        let floor_color = match panels.get(&(xpos, ypos)) {
+            Some(&color) => color,
+            None         => 0,
+        };
+        input.push_back(floor_color);
+        Flow::Continue
+    };
+    prog.run_with(vec![start_color], &mut paint).unwrap();
     panels
 }
 
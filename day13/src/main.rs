@@ -4,8 +4,9 @@ use std::collections::VecDeque;
 use std::error::Error;
 use std::fs;
 
+use intcode::stream::Flow;
+use intcode::stream::OutputHandler;
 use intcode::IntcodeProg;
-use intcode::ProgramStatus as IPS;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut prog: Vec<_> = fs::read_to_string("input.txt")?
@@ -24,38 +25,43 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-fn play_game(prog: &[i64]) -> i64 {
-    let mut prog    = IntcodeProg::new(prog);
-    let mut input   = VecDeque::new();
-    let mut output  = VecDeque::new();
-    let mut blocks  = HashSet::new();
-    let mut ballx   = -1;
-    let mut paddlex = -1;
-    loop {
-        let res = prog.exec_instr(&mut input, &mut output);
-        if output.len() == 3 {
-            let x    = output.pop_front().unwrap();
-            let y    = output.pop_front().unwrap();
-            let tile = output.pop_front().unwrap();
-            if x == -1 && y == 0 && blocks.is_empty() {
-                return tile;
-            } else if tile == 0 {
-                let _ = blocks.remove(&(x, y));
-            } else if tile == 2 {
-                let _ = blocks.insert((x, y));
-            } else if tile == 3 {
-                paddlex = x;
-            } else if tile == 4 {
-                ballx = x;
-            }
-        }
+struct Game {
+    blocks:  HashSet<(i64, i64)>,
+    ballx:   i64,
+    paddlex: i64,
+    score:   Option<i64>,
+}
 
-        if res == IPS::WaitingForInput {
-            input.push_back(match ballx.cmp(&paddlex) {
-                Ordering::Less    => -1,
-                Ordering::Equal   => 0,
-                Ordering::Greater => 1,
-            });
+impl OutputHandler<i64, 3> for Game {
+    fn output(&mut self, [x, y, tile]: [i64; 3], _: &mut VecDeque<i64>) -> Flow {
+        if x == -1 && y == 0 && self.blocks.is_empty() {
+            self.score = Some(tile);
+            return Flow::Stop;
+        } else if tile == 0 {
+            let _ = self.blocks.remove(&(x, y));
+        } else if tile == 2 {
+            let _ = self.blocks.insert((x, y));
+        } else if tile == 3 {
+            self.paddlex = x;
+        } else if tile == 4 {
+            self.ballx = x;
         }
+        Flow::Continue
+    }
+
+    fn input(&mut self, input: &mut VecDeque<i64>) -> Flow {
+        input.push_back(match self.ballx.cmp(&self.paddlex) {
+            Ordering::Less    => -1,
+            Ordering::Equal   => 0,
+            Ordering::Greater => 1,
+        });
+        Flow::Continue
     }
 }
+
+fn play_game(prog: &[i64]) -> i64 {
+    let mut prog = IntcodeProg::new(prog);
+    let mut game = Game { blocks: HashSet::new(), ballx: -1, paddlex: -1, score: None };
+    prog.run_with(Vec::new(), &mut game).unwrap();
+    game.score.unwrap()
+}
@@ -47,7 +47,7 @@ fn oxygen_bfs(prog: IntcodeProg) -> (i64, Option<IntcodeProg>) {
         for cmd in moves.iter() {
             let new_pos = next_pos(&pos, *cmd);
             if visited.get(&new_pos) == None {
-                let mut tmp_prog = prog.clone();
+                let mut tmp_prog = prog.fork();
                 let     status   = exec_move(&mut tmp_prog, *cmd);
                 if status == MoveStatus::Oxygen {
                     // Note: For part 2, Oxygen will not be found again since
@@ -1,6 +1,8 @@
 use std::error::Error;
 use std::fs;
+use std::thread;
 
+use intcode::pool::MachinePool;
 use intcode::IntcodeProg;
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -14,23 +16,45 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Resets the drone program and checks whether (x, y) is within the beam.
+fn probe(drone: &mut IntcodeProg, x: i64, y: i64) -> i64 {
+    drone.reset();
+    let output = drone.run(vec![x, y]).unwrap_or_else(|e| panic!("{}", e));
+    *output.back().unwrap()
+}
+
 fn count_beam_points(prog: &[i64]) -> i64 {
-    let mut count = 0;
-    for y in 0..50 {
-        for x in 0..50 {
-            count += IntcodeProg::exec_prog(prog, vec![x, y]).back().unwrap();
-        }
-    }
-    count
+    // The rows are probed by a few workers sharing a pool of drones.
+    let pool    = MachinePool::new(IntcodeProg::new(prog));
+    let workers = 4;
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|w| {
+                let pool = &pool;
+                s.spawn(move || {
+                    let mut drone = pool.get();
+                    let mut count = 0;
+                    for y in (w..50).step_by(workers as usize) {
+                        for x in 0..50 {
+                            count += probe(&mut drone, x, y);
+                        }
+                    }
+                    count
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    })
 }
 
 fn fit_square(prog: &[i64]) -> i64 {
-    let mut x = 0;
+    let mut drone = IntcodeProg::new(prog);
+    let mut x     = 0;
     for y in 100.. {
-        while IntcodeProg::exec_prog(prog, vec![x, y]) == [0] {
+        while probe(&mut drone, x, y) == 0 {
             x += 1;
         }
-        if IntcodeProg::exec_prog(prog, vec![x + 99, y - 99]) == [1] {
+        if probe(&mut drone, x + 99, y - 99) == 1 {
             return (x * 10000) + (y - 99);
         }
     }
@@ -0,0 +1,336 @@
+// Async execution of Intcode programs.
+//
+// A machine run with `run_async` awaits its input from an `AsyncInput`
+// whenever it executes op code 3 without buffered input, and hands every
+// output to an `AsyncOutput`. Nothing depends on a particular runtime: the
+// channel and executor below are enough to wire up feedback loops (day 7)
+// or networks (day 23) of machines in a single thread.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Wake;
+use std::task::Waker;
+use std::thread;
+
+use crate::IntcodeError;
+use crate::IntcodeProg;
+use crate::ProgramStatus;
+use crate::Word;
+
+pub trait AsyncInput<W> {
+    // Ready(None) means that no more input will ever arrive.
+    fn poll_input(&mut self, cx: &mut Context) -> Poll<Option<W>>;
+}
+
+pub trait AsyncOutput<W> {
+    fn poll_output(&mut self, cx: &mut Context, val: W) -> Poll<()>;
+}
+
+impl<W: Word> IntcodeProg<W> {
+    pub async fn run_async<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<(), IntcodeError>
+    where
+        I: AsyncInput<W>,
+        O: AsyncOutput<W>,
+    {
+        let mut inputs  = VecDeque::new();
+        let mut outputs = VecDeque::new();
+        loop {
+            let status = self.try_exec_instr(&mut inputs, &mut outputs)?;
+            while let Some(val) = outputs.pop_front() {
+                poll_fn(|cx| output.poll_output(cx, val)).await;
+            }
+            match status {
+                ProgramStatus::Success         => (),
+                ProgramStatus::Finished        => break Ok(()),
+                ProgramStatus::WaitingForInput => {
+                    match poll_fn(|cx| input.poll_input(cx)).await {
+                        Some(val) => inputs.push_back(val),
+                        None      => break Err(IntcodeError::MissingInput { ip: self.ip }),
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Shared<W> {
+    queue:   VecDeque<W>,
+    // One per waiting receiver, all of them are woken by a send.
+    wakers:  Vec<Waker>,
+    senders: usize,
+}
+
+// Wakes the waiting receivers after the borrow of the shared state ended,
+// since a waker may poll a receiver right away.
+fn wake_all(wakers: Vec<Waker>) {
+    wakers.into_iter().for_each(Waker::wake);
+}
+
+// Sending half of an unbounded single-threaded channel.
+pub struct Sender<W>(Rc<RefCell<Shared<W>>>);
+
+// Receiving half, clones share the same queue.
+#[derive(Clone)]
+pub struct Receiver<W>(Rc<RefCell<Shared<W>>>);
+
+pub fn channel<W>() -> (Sender<W>, Receiver<W>) {
+    let shared = Rc::new(RefCell::new(Shared {
+        queue:   VecDeque::new(),
+        wakers:  Vec::new(),
+        senders: 1,
+    }));
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+impl<W> Sender<W> {
+    pub fn send(&self, val: W) {
+        let wakers = {
+            let mut shared = self.0.borrow_mut();
+            shared.queue.push_back(val);
+            mem::take(&mut shared.wakers)
+        };
+        wake_all(wakers);
+    }
+}
+
+impl<W> Clone for Sender<W> {
+    fn clone(&self) -> Sender<W> {
+        self.0.borrow_mut().senders += 1;
+        Sender(self.0.clone())
+    }
+}
+
+impl<W> Drop for Sender<W> {
+    fn drop(&mut self) {
+        let wakers = {
+            let mut shared = self.0.borrow_mut();
+            shared.senders -= 1;
+            mem::take(&mut shared.wakers)
+        };
+        wake_all(wakers);
+    }
+}
+
+impl<W> Receiver<W> {
+    pub fn try_recv(&self) -> Option<W> {
+        self.0.borrow_mut().queue.pop_front()
+    }
+}
+
+impl<W> AsyncOutput<W> for Sender<W> {
+    fn poll_output(&mut self, _cx: &mut Context, val: W) -> Poll<()> {
+        self.send(val);
+        Poll::Ready(())
+    }
+}
+
+impl<W> AsyncInput<W> for Receiver<W> {
+    fn poll_input(&mut self, cx: &mut Context) -> Poll<Option<W>> {
+        let mut shared = self.0.borrow_mut();
+        if let Some(val) = shared.queue.pop_front() {
+            Poll::Ready(Some(val))
+        } else if shared.senders == 0 {
+            Poll::Ready(None)
+        } else {
+            if !shared.wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                shared.wakers.push(cx.waker().clone());
+            }
+            Poll::Pending
+        }
+    }
+}
+
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+struct Task<'a> {
+    future: Pin<Box<dyn Future<Output = ()> + 'a>>,
+    woken:  Arc<Flag>,
+}
+
+// Minimal single-threaded executor. Tasks are polled whenever they have
+// been woken, until all of them completed or none of them can proceed.
+#[derive(Default)]
+pub struct Executor<'a> {
+    tasks: Vec<Task<'a>>,
+}
+
+impl<'a> Executor<'a> {
+    pub fn new() -> Executor<'a> {
+        Executor { tasks: Vec::new() }
+    }
+
+    pub fn spawn<F: Future<Output = ()> + 'a>(&mut self, future: F) {
+        self.tasks.push(Task {
+            future: Box::pin(future),
+            woken:  Arc::new(Flag(AtomicBool::new(true))),
+        });
+    }
+
+    // Returns the number of tasks that are stuck (waiting for something
+    // that will never happen), zero if all tasks completed.
+    pub fn run(&mut self) -> usize {
+        loop {
+            let mut progress = false;
+            let mut i = 0;
+            while i < self.tasks.len() {
+                let task = &mut self.tasks[i];
+                if task.woken.0.swap(false, Ordering::SeqCst) {
+                    progress  = true;
+                    let waker = Waker::from(task.woken.clone());
+                    let mut cx = Context::from_waker(&waker);
+                    if task.future.as_mut().poll(&mut cx).is_ready() {
+                        self.tasks.swap_remove(i);
+                        continue;
+                    }
+                }
+                i += 1;
+            }
+            if !progress {
+                return self.tasks.len();
+            }
+        }
+    }
+}
+
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Runs a single future to completion, parking the thread while it is pending.
+// Only useful if the future is woken from another thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = Box::pin(future);
+    let waker      = Waker::from(Arc::new(Unpark(thread::current())));
+    let mut cx     = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(val) = future.as_mut().poll(&mut cx) {
+            return val;
+        }
+        thread::park();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn feedback_loop() {
+        // Example from day 7, part 2.
+        let prog = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let phases = [9, 8, 7, 6, 5];
+        let (senders, receivers): (Vec<_>, Vec<_>) = phases.iter().map(|_| channel()).unzip();
+        for (tx, &phase) in senders.iter().zip(phases.iter()) {
+            tx.send(phase);
+        }
+        senders[0].send(0);
+        let result = receivers[0].clone();
+
+        let mut executor = Executor::new();
+        for (i, mut rx) in receivers.into_iter().enumerate() {
+            let mut tx   = senders[(i + 1) % phases.len()].clone();
+            let mut prog = IntcodeProg::new(&prog);
+            executor.spawn(async move { prog.run_async(&mut rx, &mut tx).await.unwrap() });
+        }
+        assert_eq!(executor.run(), 0);
+        assert_eq!(result.try_recv(), Some(139629729));
+    }
+
+    #[test]
+    fn stuck_and_closed_inputs() {
+        let prog = vec![3, 0, 4, 0, 99];
+
+        // Nobody ever sends anything.
+        let (tx, mut rx) = channel();
+        let (mut out, _) = channel();
+        let mut executor = Executor::new();
+        let mut machine  = IntcodeProg::new(&prog);
+        executor.spawn(async move {
+            let _ = machine.run_async(&mut rx, &mut out).await;
+        });
+        assert_eq!(executor.run(), 1);
+
+        // All senders are gone.
+        drop(tx);
+        assert_eq!(executor.run(), 0);
+    }
+
+    #[test]
+    fn shared_receiver() {
+        // Two machines wait on clones of the same receiver, both have to be
+        // woken once input arrives.
+        let (tx, rx)     = channel();
+        let (out, res)   = channel();
+        let mut executor = Executor::new();
+        for _ in 0..2 {
+            let (mut rx, mut out) = (rx.clone(), out.clone());
+            let mut machine       = IntcodeProg::new(&[3, 0, 4, 0, 99]);
+            executor.spawn(async move { machine.run_async(&mut rx, &mut out).await.unwrap() });
+        }
+        assert_eq!(executor.run(), 2);
+        tx.send(1);
+        tx.send(2);
+        assert_eq!(executor.run(), 0);
+        let mut values = vec![res.try_recv().unwrap(), res.try_recv().unwrap()];
+        values.sort_unstable();
+        assert_eq!(values, vec![1, 2]);
+    }
+
+    struct ThreadInput(mpsc::Receiver<i64>);
+
+    impl AsyncInput<i64> for ThreadInput {
+        fn poll_input(&mut self, cx: &mut Context) -> Poll<Option<i64>> {
+            // Good enough for a test: hand the waker to a helper thread.
+            match self.0.try_recv() {
+                Ok(val) => Poll::Ready(Some(val)),
+                Err(mpsc::TryRecvError::Disconnected) => Poll::Ready(None),
+                Err(mpsc::TryRecvError::Empty) => {
+                    let waker = cx.waker().clone();
+                    thread::spawn(move || {
+                        thread::sleep(std::time::Duration::from_millis(1));
+                        waker.wake();
+                    });
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn external_input() {
+        let (tx, rx)       = mpsc::channel();
+        let (mut out, res) = channel();
+        let mut machine    = IntcodeProg::new(&[3, 0, 1002, 0, 3, 0, 4, 0, 99]);
+        let producer       = thread::spawn(move || {
+            thread::sleep(std::time::Duration::from_millis(10));
+            tx.send(14).unwrap();
+        });
+        block_on(machine.run_async(&mut ThreadInput(rx), &mut out)).unwrap();
+        producer.join().unwrap();
+        assert_eq!(res.try_recv(), Some(42));
+    }
+}
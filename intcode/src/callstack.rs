@@ -0,0 +1,149 @@
+// Shadow call stack for programs using the relative base as frame pointer.
+//
+// Calls are recognized at run time by the calling convention of the puzzle
+// programs and the compiler: the caller writes the return address to
+// [rb+0] and then jumps to the function, which moves the relative base past
+// its frame and finally jumps back to the return address with the relative
+// base restored. A jump to the return address of a frame further down the
+// stack (with its relative base) unwinds all frames above it.
+
+use std::collections::VecDeque;
+
+use crate::symbols::SymbolMap;
+use crate::IntcodeError;
+use crate::IntcodeProg;
+use crate::ProgramStatus;
+use crate::Word;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    // Position of the calling jump.
+    pub call_site: usize,
+    pub entry:     usize,
+    pub ret:       usize,
+    // Relative base of the caller.
+    pub rel_base:  i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallStack {
+    frames:  Vec<Frame>,
+    // Return address written to [rb+0] since the last jump.
+    pending: Option<usize>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack::default()
+    }
+
+    // Active calls, outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.pending = None;
+    }
+
+    // Updates the stack after the instruction at `ip` was executed. `write`
+    // is the cell it wrote, `rel_base` the relative base and `next_ip` the
+    // instruction pointer after it.
+    pub fn observe(
+        &mut self,
+        ip:       usize,
+        op_code:  i64,
+        write:    Option<(usize, i128)>,
+        rel_base: i64,
+        next_ip:  usize,
+    ) {
+        match op_code {
+            5 | 6 if next_ip != ip + 3 => {
+                let is_ret = |f: &Frame| f.ret == next_ip && f.rel_base == rel_base;
+                if let Some(i) = self.frames.iter().rposition(is_ret) {
+                    self.frames.truncate(i);
+                } else if let Some(ret) = self.pending {
+                    self.frames.push(Frame { call_site: ip, entry: next_ip, ret, rel_base });
+                }
+                self.pending = None;
+            }
+            9 => self.pending = None,
+            _ => {
+                if let Some((addr, val)) = write {
+                    if addr as i64 == rel_base && val >= 0 {
+                        self.pending = Some(val as usize);
+                    }
+                }
+            }
+        }
+    }
+
+    // Executes a single instruction and updates the stack.
+    pub fn step<W: Word>(
+        &mut self,
+        prog:   &mut IntcodeProg<W>,
+        input:  &mut VecDeque<W>,
+        output: &mut VecDeque<W>,
+    ) -> Result<ProgramStatus, IntcodeError> {
+        let ip      = prog.ip();
+        let op_code = (prog.peek(ip as i64)?.to_i128() % 100) as i64;
+        let status  = prog.try_exec_instr(input, output)?;
+        if status == ProgramStatus::Success {
+            let write = prog.last_write().map(|(addr, val)| (addr, val.to_i128()));
+            self.observe(ip, op_code, write, prog.rel_base(), prog.ip());
+        }
+        Ok(status)
+    }
+
+    // Position and function name of every frame, innermost first, with the
+    // current position `ip`. Functions are named by symbol or as `f<entry>`
+    // like the decompiler does, the outermost one is `main`.
+    pub fn backtrace(&self, ip: usize, symbols: &SymbolMap) -> Vec<(usize, String)> {
+        let mut trace = Vec::with_capacity(self.frames.len() + 1);
+        let mut pos   = ip;
+        for frame in self.frames.iter().rev() {
+            let name = symbols.name(frame.entry).unwrap_or_else(|| format!("f{}", frame.entry));
+            trace.push((pos, name));
+            pos = frame.call_site;
+        }
+        trace.push((pos, String::from("main")));
+        trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+
+    #[test]
+    fn nested_calls() {
+        let prog = compiler::compile(
+            "fn fact(n) { if (n < 2) { output(n); return 1; } return n * fact(n - 1); }
+             fn main() { output(fact(input())); output(0); }",
+        )
+        .unwrap();
+        let mut machine = IntcodeProg::new(&prog);
+        let mut calls   = CallStack::new();
+        let mut input   = VecDeque::from(vec![4]);
+        let mut output  = VecDeque::new();
+        let mut deepest = None;
+        while calls.step(&mut machine, &mut input, &mut output).unwrap() == ProgramStatus::Success {
+            if output.len() == 1 && deepest.is_none() {
+                deepest = Some(calls.backtrace(machine.ip(), &SymbolMap::default()));
+            }
+        }
+        // main and fact(4) down to fact(1).
+        let deepest = deepest.unwrap();
+        let names: Vec<_> = deepest.iter().map(|(_, name)| name.as_str()).collect();
+        assert_eq!(names, vec!["f10", "f10", "f10", "f10", "f75", "main"]);
+        assert!(deepest[1..].iter().all(|&(pos, _)| machine.mem()[pos] == 1105));
+        assert_eq!(output, vec![1, 24, 0]);
+        assert_eq!(calls.depth(), 0);
+    }
+}
@@ -0,0 +1,285 @@
+// Control-flow graph of the statically reachable code.
+//
+// Calls are recognized by the calling convention used by the puzzle
+// programs: the caller stores the return address at [rb+0] and jumps to the
+// function, which moves the relative base past its frame (`arb N`), and
+// returns by jumping to [rb+0] after restoring the relative base.
+
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::disasm;
+use crate::disasm::Instr;
+use crate::disasm::Mode;
+use crate::disasm::Op;
+use crate::disasm::Param;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Exit {
+    // Unconditional jump or fall-through into the next block.
+    Goto(usize),
+    // Conditional jump (the last instruction of the block) to `target`,
+    // otherwise execution continues at `next`.
+    Branch { target: usize, next: usize },
+    Call { target: usize, ret: usize },
+    // Call through a function pointer.
+    IndirectCall { ret: usize },
+    Return,
+    // Jump to a computed address, possibly falling through to `next`.
+    Indirect { next: Option<usize> },
+    Halt,
+    // Execution runs into memory that does not decode as an instruction.
+    Invalid,
+}
+
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start:  usize,
+    pub instrs: Vec<Instr>,
+    pub exit:   Exit,
+}
+
+impl Block {
+    pub fn successors(&self) -> Vec<usize> {
+        match self.exit {
+            Exit::Goto(next)                 => vec![next],
+            Exit::Branch { target, next }    => vec![target, next],
+            Exit::Call { ret, .. }           => vec![ret],
+            Exit::IndirectCall { ret }       => vec![ret],
+            Exit::Indirect { next: Some(n) } => vec![n],
+            _                                => Vec::new(),
+        }
+    }
+
+    // The last instruction if it is the jump ending the block.
+    pub fn jump(&self) -> Option<&Instr> {
+        self.instrs.last().filter(|i| i.is_jump())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub entry:  usize,
+    // Frame size if the function starts with the `arb N` prologue.
+    pub frame:  Option<i64>,
+    pub blocks: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+}
+
+const RB0: Param = Param { mode: Mode::Relative, value: 0 };
+
+fn return_site(instr: &Instr) -> Option<usize> {
+    match instr.const_write() {
+        Some((dst, val)) if dst == RB0 && val >= 0 => Some(val as usize),
+        _                                          => None,
+    }
+}
+
+impl Cfg {
+    pub fn build(mem: &[i64]) -> Cfg {
+        let instrs = disasm::reachable(mem);
+
+        let mut leaders = BTreeSet::new();
+        leaders.insert(0);
+        for instr in instrs.values() {
+            if instr.is_jump() {
+                leaders.extend(instr.jump_target());
+                leaders.insert(instr.next());
+            }
+            leaders.extend(return_site(instr));
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut iter   = instrs.values().peekable();
+        while let Some(first) = iter.next() {
+            let mut block = Block {
+                start:  first.addr,
+                instrs: vec![first.clone()],
+                exit:   Exit::Invalid,
+            };
+            loop {
+                let last = block.instrs.last().unwrap();
+                if last.is_jump() || last.op == Op::Halt {
+                    break;
+                }
+                match iter.peek() {
+                    Some(i) if i.addr == last.next() && !leaders.contains(&i.addr) => {
+                        block.instrs.push(iter.next().unwrap().clone());
+                    }
+                    _ => break,
+                }
+            }
+            block.exit = Cfg::block_exit(&block.instrs, &instrs);
+            blocks.insert(block.start, block);
+        }
+        Cfg { blocks }
+    }
+
+    fn block_exit(block: &[Instr], instrs: &BTreeMap<usize, Instr>) -> Exit {
+        let last = block.last().unwrap();
+        let next = last.next();
+        let cont = if instrs.contains_key(&next) { Exit::Goto(next) } else { Exit::Invalid };
+        if last.op == Op::Halt {
+            return Exit::Halt;
+        } else if !last.is_jump() {
+            return cont;
+        }
+        let ret = match block.len() {
+            n if n >= 2 => return_site(&block[n - 2]),
+            _           => None,
+        };
+        match (last.always_taken(), last.jump_target()) {
+            (Some(false), _) => cont,
+            (Some(true), Some(target)) => match ret {
+                Some(ret) => Exit::Call { target, ret },
+                None      => Exit::Goto(target),
+            },
+            (Some(true), None) if last.params[1] == RB0 => Exit::Return,
+            (Some(true), None) => match ret {
+                Some(ret) => Exit::IndirectCall { ret },
+                None      => Exit::Indirect { next: None },
+            },
+            (None, Some(target)) => Exit::Branch { target, next },
+            (None, None) => Exit::Indirect { next: Some(next) },
+        }
+    }
+
+    // Splits the graph into functions: the program entry and all call
+    // targets, each with the blocks reachable without following calls.
+    pub fn functions(&self) -> Vec<Function> {
+        let mut entries = BTreeSet::new();
+        entries.insert(0);
+        for block in self.blocks.values() {
+            if let Exit::Call { target, .. } = block.exit {
+                entries.insert(target);
+            }
+        }
+
+        entries
+            .into_iter()
+            .filter(|entry| self.blocks.contains_key(entry))
+            .map(|entry| {
+                let mut blocks = BTreeSet::new();
+                let mut todo   = vec![entry];
+                while let Some(b) = todo.pop() {
+                    if self.blocks.contains_key(&b) && blocks.insert(b) {
+                        todo.extend(self.blocks[&b].successors());
+                    }
+                }
+                let first = &self.blocks[&entry].instrs[0];
+                let frame = match (first.op, first.params.first().and_then(|p| p.imm())) {
+                    (Op::Arb, Some(n)) if n > 0 && entry != 0 => Some(n),
+                    _                                          => None,
+                };
+                Function { entry, frame, blocks }
+            })
+            .collect()
+    }
+
+    // Immediate post dominator of every block of the function that has
+    // one, the block where all paths from it join again.
+    pub fn post_dominators(&self, func: &Function) -> HashMap<usize, usize> {
+        let succs = |b: usize| -> Vec<usize> {
+            self.blocks[&b].successors().into_iter().filter(|s| func.blocks.contains(s)).collect()
+        };
+        let nodes: Vec<_> = func.blocks.iter().copied().collect();
+        let all: BTreeSet<_> = nodes.iter().copied().collect();
+
+        // Blocks that cannot reach an exit (endless loops) have no
+        // meaningful post dominator.
+        let mut reaches_exit: HashSet<_> = nodes
+            .iter()
+            .copied()
+            .filter(|&b| succs(b).is_empty())
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in nodes.iter() {
+                if !reaches_exit.contains(&b)
+                    && succs(b).iter().any(|s| reaches_exit.contains(s))
+                {
+                    reaches_exit.insert(b);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut pdom: HashMap<usize, BTreeSet<usize>> = nodes
+            .iter()
+            .map(|&b| {
+                if succs(b).is_empty() {
+                    (b, std::iter::once(b).collect())
+                } else {
+                    (b, all.clone())
+                }
+            })
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &b in nodes.iter().rev() {
+                let next = succs(b);
+                if next.is_empty() {
+                    continue;
+                }
+                let mut new = pdom[&next[0]].clone();
+                for s in next[1..].iter() {
+                    new = new.intersection(&pdom[s]).copied().collect();
+                }
+                new.insert(b);
+                if new != pdom[&b] {
+                    pdom.insert(b, new);
+                    changed = true;
+                }
+            }
+        }
+
+        let mut ipdom = HashMap::new();
+        for &b in nodes.iter().filter(|b| reaches_exit.contains(b)) {
+            let size = pdom[&b].len();
+            let idom = pdom[&b]
+                .iter()
+                .find(|&&d| d != b && pdom[&d].len() + 1 == size)
+                .copied();
+            if let Some(d) = idom {
+                ipdom.insert(b, d);
+            }
+        }
+        ipdom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_and_branches() {
+        // main: read a value, call f(value) if it is non-zero, halt.
+        // f:    output its argument and return.
+        let mem = vec![
+            109, 100, 3, 50, 1006, 50, 19, 21001, 50, 0, 1, 21101, 0, 19, 0, 1105, 1, 20, 99, 99,
+            109, 2, 204, -1, 109, -2, 2105, 1, 0,
+        ];
+        let cfg = Cfg::build(&mem);
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), vec![0, 7, 19, 20]);
+        assert_eq!(cfg.blocks[&0].exit, Exit::Branch { target: 19, next: 7 });
+        assert_eq!(cfg.blocks[&7].exit, Exit::Call { target: 20, ret: 19 });
+        assert_eq!(cfg.blocks[&19].exit, Exit::Halt);
+        assert_eq!(cfg.blocks[&20].exit, Exit::Return);
+
+        let funcs = cfg.functions();
+        assert_eq!(funcs.len(), 2);
+        assert_eq!(funcs[0].blocks.iter().copied().collect::<Vec<_>>(), vec![0, 7, 19]);
+        assert_eq!(funcs[0].frame, None);
+        assert_eq!(funcs[1].entry, 20);
+        assert_eq!(funcs[1].frame, Some(2));
+    }
+}
@@ -0,0 +1,1048 @@
+// Compiler for a small structured language targeting Intcode.
+//
+//     var primes[100];                 // global array, zero-initialized
+//     var msg[] = "done";              // global array with a trailing 0
+//     var count = 0;                   // global scalar
+//
+//     fn square(n) {
+//         var d = n * n;               // local variable
+//         return d;
+//     }
+//
+//     fn main() {
+//         var n = input();
+//         while (count < n) {
+//             primes[count] = square(count);
+//             count = count + 1;
+//         }
+//     }
+//
+// Values are integers. Operators are `+ - * < <= > >= == != && || !` and
+// unary `-`; `&&` and `||` evaluate both operands. Arrays are global only
+// and indexed with `a[i]`. Statements are `var`, assignments, `if`/`else`,
+// `while`, `break`, `continue` and `return`. `input()` reads a value and
+// `output(x)` writes one. Character literals like 'a' are numbers.
+//
+// Functions use the calling convention understood by the `cfg` module: the
+// caller puts the arguments at [rb+1], [rb+2], ... and the return address
+// at [rb+0] before jumping to the function, which moves the relative base
+// past its frame (`arb N`) and jumps back through [rb+0]. The return value
+// is passed in [rb+1]. The program starts by calling `main` and halts when
+// it returns. Globals follow the code, then a few scratch cells, then the
+// stack.
+//
+// Programs never write to their code. An array element with a computed
+// index is accessed through [rb+0] after moving the relative base onto it,
+// so the current relative base is kept in a scratch cell as well.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub line: usize,
+    pub msg:  String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+fn error<T>(line: usize, msg: String) -> Result<T, CompileError> {
+    Err(CompileError { line, msg })
+}
+
+pub fn compile(src: &str) -> Result<Vec<i64>, CompileError> {
+    let tokens  = lex(src)?;
+    let program = Parser { tokens, pos: 0 }.program()?;
+    Codegen::new(&program)?.program()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(i64),
+    Ident(String),
+    Str(String),
+    Sym(&'static str),
+    Eof,
+}
+
+const SYMBOLS: [&str; 21] = [
+    "==", "!=", "<=", ">=", "&&", "||", "(", ")", "{", "}", "[", "]", ";", ",", "=", "<", ">",
+    "+", "-", "*", "!",
+];
+
+fn lex(src: &str) -> Result<Vec<(Tok, usize)>, CompileError> {
+    let chars      = src.chars().collect::<Vec<_>>();
+    let mut tokens = Vec::new();
+    let mut line   = 1;
+    let mut i      = 0;
+    while i < chars.len() {
+        let c    = chars[i];
+        let rest = chars[i..].iter().take(2).collect::<String>();
+        if c == '\n' {
+            line += 1;
+            i    += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if rest == "//" {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let text = chars[start..i].iter().collect::<String>();
+            match text.parse() {
+                Ok(n)  => tokens.push((Tok::Num(n), line)),
+                Err(_) => return error(line, format!("number {} is too large", text)),
+            }
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((Tok::Ident(chars[start..i].iter().collect()), line));
+        } else if c == '\'' {
+            match (chars.get(i + 1), chars.get(i + 2)) {
+                (Some(&c), Some('\'')) => tokens.push((Tok::Num(c as i64), line)),
+                _                      => return error(line, String::from("invalid character literal")),
+            }
+            i += 3;
+        } else if c == '"' {
+            let start = i + 1;
+            i = start;
+            while i < chars.len() && chars[i] != '"' && chars[i] != '\n' {
+                i += 1;
+            }
+            if chars.get(i) != Some(&'"') {
+                return error(line, String::from("unterminated string"));
+            }
+            let text = chars[start..i].iter().collect::<String>();
+            tokens.push((Tok::Str(text.replace("\\n", "\n")), line));
+            i += 1;
+        } else if let Some(sym) = SYMBOLS.iter().find(|s| rest.starts_with(*s)) {
+            tokens.push((Tok::Sym(sym), line));
+            i += sym.len();
+        } else {
+            return error(line, format!("unexpected character '{}'", c));
+        }
+    }
+    tokens.push((Tok::Eof, line));
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Num(i64),
+    Var(String),
+    Index(String, Box<Expr>),
+    Call(String, Vec<Expr>),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+enum StmtKind {
+    Var(String, Option<Expr>),
+    Assign(Expr, Expr),
+    Expr(Expr),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Break,
+    Continue,
+}
+
+#[derive(Debug, Clone)]
+struct Stmt {
+    kind: StmtKind,
+    line: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Func {
+    name:   String,
+    params: Vec<String>,
+    body:   Vec<Stmt>,
+    line:   usize,
+}
+
+#[derive(Debug, Clone)]
+struct Global {
+    name:  String,
+    array: bool,
+    init:  Vec<i64>,
+    line:  usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Program {
+    globals: Vec<Global>,
+    funcs:   Vec<Func>,
+}
+
+struct Parser {
+    tokens: Vec<(Tok, usize)>,
+    pos:    usize,
+}
+
+fn const_eval(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Num(n)            => Some(*n),
+        Expr::Unary("-", a)     => Some(const_eval(a)?.wrapping_neg()),
+        Expr::Binary(op, a, b)  => {
+            let (a, b) = (const_eval(a)?, const_eval(b)?);
+            match *op {
+                "+" => Some(a.wrapping_add(b)),
+                "-" => Some(a.wrapping_sub(b)),
+                "*" => Some(a.wrapping_mul(b)),
+                _   => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].1
+    }
+
+    fn next(&mut self) -> Tok {
+        let tok = self.tokens[self.pos].0.clone();
+        if tok != Tok::Eof {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn eat(&mut self, sym: &str) -> bool {
+        if *self.peek() == Tok::Sym(SYMBOLS.iter().find(|s| **s == sym).unwrap()) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, sym: &str) -> Result<(), CompileError> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            error(self.line(), format!("expected '{}'", sym))
+        }
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        if *self.peek() == Tok::Ident(word.to_string()) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, CompileError> {
+        let line = self.line();
+        match self.next() {
+            Tok::Ident(name) if !is_keyword(&name) => Ok(name),
+            _                                      => error(line, String::from("expected a name")),
+        }
+    }
+
+    fn program(mut self) -> Result<Program, CompileError> {
+        let mut program = Program::default();
+        while *self.peek() != Tok::Eof {
+            if self.keyword("var") {
+                program.globals.push(self.global()?);
+            } else if self.keyword("fn") {
+                program.funcs.push(self.func()?);
+            } else {
+                return error(self.line(), String::from("expected 'var' or 'fn'"));
+            }
+        }
+        Ok(program)
+    }
+
+    fn global(&mut self) -> Result<Global, CompileError> {
+        let line = self.line();
+        let name = self.ident()?;
+        if !self.eat("[") {
+            let init = if self.eat("=") { self.constant()? } else { 0 };
+            self.expect(";")?;
+            return Ok(Global { name, array: false, init: vec![init], line });
+        }
+        let size = match self.next() {
+            Tok::Num(n)   => Some(n as usize),
+            Tok::Sym("]") => None,
+            _             => return error(line, String::from("expected an array size")),
+        };
+        if size.is_some() {
+            self.expect("]")?;
+        }
+        let mut init = Vec::new();
+        if self.eat("=") {
+            if let Tok::Str(text) = self.peek().clone() {
+                self.pos += 1;
+                init.extend(text.chars().map(|c| c as i64));
+                init.push(0);
+            } else {
+                self.expect("{")?;
+                while !self.eat("}") {
+                    init.push(self.constant()?);
+                    if !self.eat(",") {
+                        self.expect("}")?;
+                        break;
+                    }
+                }
+            }
+        }
+        self.expect(";")?;
+        match size {
+            Some(n) if n < init.len() => return error(line, format!("too many initializers for {}", name)),
+            Some(n)                   => init.resize(n, 0),
+            None if init.is_empty()   => return error(line, format!("array {} needs a size", name)),
+            None                      => (),
+        }
+        if init.is_empty() {
+            return error(line, format!("array {} is empty", name));
+        }
+        Ok(Global { name, array: true, init, line })
+    }
+
+    fn constant(&mut self) -> Result<i64, CompileError> {
+        let line = self.line();
+        match const_eval(&self.expr()?) {
+            Some(n) => Ok(n),
+            None    => error(line, String::from("expected a constant")),
+        }
+    }
+
+    fn func(&mut self) -> Result<Func, CompileError> {
+        let line       = self.line();
+        let name       = self.ident()?;
+        let mut params = Vec::new();
+        self.expect("(")?;
+        while !self.eat(")") {
+            params.push(self.ident()?);
+            if !self.eat(",") {
+                self.expect(")")?;
+                break;
+            }
+        }
+        let body = self.block()?;
+        Ok(Func { name, params, body, line })
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, CompileError> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            if *self.peek() == Tok::Eof {
+                return error(self.line(), String::from("expected '}'"));
+            }
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, CompileError> {
+        let line = self.line();
+        let kind = if self.keyword("var") {
+            let name = self.ident()?;
+            if *self.peek() == Tok::Sym("[") {
+                return error(line, String::from("arrays must be global"));
+            }
+            let init = if self.eat("=") { Some(self.expr()?) } else { None };
+            self.expect(";")?;
+            StmtKind::Var(name, init)
+        } else if self.keyword("if") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            let then = self.block()?;
+            let els  = if !self.keyword("else") {
+                Vec::new()
+            } else if *self.peek() == Tok::Ident(String::from("if")) {
+                vec![self.stmt()?]
+            } else {
+                self.block()?
+            };
+            StmtKind::If(cond, then, els)
+        } else if self.keyword("while") {
+            self.expect("(")?;
+            let cond = self.expr()?;
+            self.expect(")")?;
+            StmtKind::While(cond, self.block()?)
+        } else if self.keyword("return") {
+            let value = if *self.peek() == Tok::Sym(";") { None } else { Some(self.expr()?) };
+            self.expect(";")?;
+            StmtKind::Return(value)
+        } else if self.keyword("break") {
+            self.expect(";")?;
+            StmtKind::Break
+        } else if self.keyword("continue") {
+            self.expect(";")?;
+            StmtKind::Continue
+        } else {
+            let expr = self.expr()?;
+            let kind = if self.eat("=") {
+                match expr {
+                    Expr::Var(_) | Expr::Index(_, _) => StmtKind::Assign(expr, self.expr()?),
+                    _ => return error(line, String::from("invalid assignment target")),
+                }
+            } else {
+                StmtKind::Expr(expr)
+            };
+            self.expect(";")?;
+            kind
+        };
+        Ok(Stmt { kind, line })
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    // Binary operators by increasing precedence.
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        const LEVELS: [&[&str]; 5] = [&["||"], &["&&"], &["==", "!="], &["<", "<=", ">", ">="], &["+", "-"]];
+        if level == LEVELS.len() {
+            return self.product();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(&op) = LEVELS[level].iter().find(|op| *self.peek() == Tok::Sym(op)) {
+            self.pos += 1;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.binary(level + 1)?));
+        }
+        Ok(lhs)
+    }
+
+    fn product(&mut self) -> Result<Expr, CompileError> {
+        let mut lhs = self.unary()?;
+        while self.eat("*") {
+            lhs = Expr::Binary("*", Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            Ok(Expr::Unary("-", Box::new(self.unary()?)))
+        } else if self.eat("!") {
+            Ok(Expr::Unary("!", Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, CompileError> {
+        let line = self.line();
+        match self.next() {
+            Tok::Num(n) => Ok(Expr::Num(n)),
+            Tok::Sym("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Tok::Ident(name) if !is_keyword(&name) => {
+                if self.eat("(") {
+                    let mut args = Vec::new();
+                    while !self.eat(")") {
+                        args.push(self.expr()?);
+                        if !self.eat(",") {
+                            self.expect(")")?;
+                            break;
+                        }
+                    }
+                    Ok(Expr::Call(name, args))
+                } else if self.eat("[") {
+                    let index = self.expr()?;
+                    self.expect("]")?;
+                    Ok(Expr::Index(name, Box::new(index)))
+                } else {
+                    Ok(Expr::Var(name))
+                }
+            }
+            _ => error(line, String::from("expected an expression")),
+        }
+    }
+}
+
+fn is_keyword(name: &str) -> bool {
+    ["var", "fn", "if", "else", "while", "return", "break", "continue"].contains(&name)
+}
+
+// Instruction operands. Slots are frame slots of the current function,
+// whose relative offset is only known once the frame size is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Opnd {
+    Imm(i64),
+    // Address of a label, immediate.
+    Label(usize),
+    // Cell of a global plus offset, position mode.
+    Global(usize, i64),
+    // Address of a global, immediate.
+    Addr(usize),
+    Slot(i64),
+    Rel(i64),
+    // Frame size times the factor, immediate.
+    Frame(i64),
+    // Start of the stack, immediate.
+    Stack,
+    // Scratch cell, position mode.
+    Scratch(i64),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Fix {
+    Label(usize),
+    Global(usize, i64),
+    Slot(i64),
+    Frame(i64),
+    Stack,
+    Scratch(i64),
+}
+
+// Scratch cells: the relative base, an offset and a value.
+const FP: Opnd     = Opnd::Scratch(0);
+const OFFSET: Opnd = Opnd::Scratch(1);
+const VALUE: Opnd  = Opnd::Scratch(2);
+
+struct Codegen<'a> {
+    program:  &'a Program,
+    code:     Vec<i64>,
+    fixups:   Vec<(usize, Fix)>,
+    labels:   Vec<Option<usize>>,
+    globals:  HashMap<&'a str, usize>,
+    funcs:    HashMap<&'a str, (usize, usize)>,
+    // State of the function being compiled.
+    scopes:   Vec<HashMap<&'a str, i64>>,
+    slots:    i64,
+    temps:    i64,
+    max_slot: i64,
+    loops:    Vec<(usize, usize)>,
+    ret:      usize,
+}
+
+impl<'a> Codegen<'a> {
+    fn new(program: &'a Program) -> Result<Codegen<'a>, CompileError> {
+        let mut gen = Codegen {
+            program,
+            code:     Vec::new(),
+            fixups:   Vec::new(),
+            labels:   Vec::new(),
+            globals:  HashMap::new(),
+            funcs:    HashMap::new(),
+            scopes:   Vec::new(),
+            slots:    0,
+            temps:    0,
+            max_slot: 0,
+            loops:    Vec::new(),
+            ret:      0,
+        };
+        for (i, global) in program.globals.iter().enumerate() {
+            if gen.globals.insert(&global.name, i).is_some() {
+                return error(global.line, format!("{} is defined twice", global.name));
+            }
+        }
+        for func in program.funcs.iter() {
+            if ["input", "output"].contains(&func.name.as_str()) {
+                return error(func.line, format!("{} is a built-in function", func.name));
+            }
+            let label = gen.label();
+            if gen.funcs.insert(&func.name, (label, func.params.len())).is_some() {
+                return error(func.line, format!("function {} is defined twice", func.name));
+            }
+        }
+        Ok(gen)
+    }
+
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn emit(&mut self, op: i64, params: &[Opnd]) {
+        let mut instr = op;
+        let mut scale = 100;
+        let at        = self.code.len() + 1;
+        let mut words = Vec::new();
+        for (i, &p) in params.iter().enumerate() {
+            let (mode, value, fix) = match p {
+                Opnd::Imm(n)       => (1, n, None),
+                Opnd::Label(l)     => (1, 0, Some(Fix::Label(l))),
+                Opnd::Global(g, o) => (0, 0, Some(Fix::Global(g, o))),
+                Opnd::Addr(g)      => (1, 0, Some(Fix::Global(g, 0))),
+                Opnd::Slot(s)      => (2, 0, Some(Fix::Slot(s))),
+                Opnd::Rel(r)       => (2, r, None),
+                Opnd::Frame(f)     => (1, 0, Some(Fix::Frame(f))),
+                Opnd::Stack        => (1, 0, Some(Fix::Stack)),
+                Opnd::Scratch(k)   => (0, 0, Some(Fix::Scratch(k))),
+            };
+            instr += mode * scale;
+            scale *= 10;
+            words.push(value);
+            if let Some(fix) = fix {
+                self.fixups.push((at + i, fix));
+            }
+        }
+        self.code.push(instr);
+        self.code.extend(words);
+    }
+
+    fn jump(&mut self, label: usize) {
+        self.emit(5, &[Opnd::Imm(1), Opnd::Label(label)]);
+    }
+
+    fn temp(&mut self) -> Opnd {
+        let slot      = self.slots + self.temps;
+        self.temps   += 1;
+        self.max_slot = self.max_slot.max(slot);
+        Opnd::Slot(slot)
+    }
+
+    fn program(mut self) -> Result<Vec<i64>, CompileError> {
+        let main = match self.funcs.get("main") {
+            Some(&(label, 0)) => label,
+            Some(_)           => return error(1, String::from("main must not take arguments")),
+            None              => return error(1, String::from("missing function main")),
+        };
+        let halt = self.label();
+        self.emit(9, &[Opnd::Stack]);
+        self.emit(1, &[Opnd::Imm(0), Opnd::Label(halt), Opnd::Rel(0)]);
+        self.jump(main);
+        self.place(halt);
+        self.emit(99, &[]);
+        for func in self.program.funcs.iter() {
+            self.func(func)?;
+        }
+
+        // Globals follow the code, the scratch cells follow the globals and
+        // the stack follows the scratch cells.
+        let mut addrs = Vec::new();
+        for global in self.program.globals.iter() {
+            addrs.push(self.code.len());
+            self.code.extend_from_slice(&global.init);
+        }
+        let scratch = self.code.len() as i64;
+        let stack   = scratch + 3;
+        self.code.extend_from_slice(&[stack, 0, 0]);
+        for &(at, fix) in self.fixups.iter() {
+            self.code[at] = match fix {
+                Fix::Label(l)     => self.labels[l].unwrap() as i64,
+                Fix::Global(g, o) => addrs[g] as i64 + o,
+                Fix::Stack        => stack,
+                Fix::Scratch(k)   => scratch + k,
+                Fix::Slot(_) | Fix::Frame(_) => unreachable!(),
+            };
+        }
+        Ok(self.code)
+    }
+
+    fn func(&mut self, func: &'a Func) -> Result<(), CompileError> {
+        let fixups = self.fixups.len();
+        self.place(self.funcs[func.name.as_str()].0);
+        self.ret = self.label();
+        self.scopes.push(HashMap::new());
+        for (i, param) in func.params.iter().enumerate() {
+            if self.scopes[0].insert(param, i as i64 + 1).is_some() {
+                return error(func.line, format!("parameter {} is defined twice", param));
+            }
+        }
+        self.slots    = func.params.len() as i64 + 1;
+        self.max_slot = self.slots.max(2) - 1;
+
+        self.emit(9, &[Opnd::Frame(1)]);
+        self.emit(1, &[FP, Opnd::Frame(1), FP]);
+        self.block(&func.body)?;
+        self.emit(1, &[Opnd::Imm(0), Opnd::Imm(0), Opnd::Slot(1)]);
+        let ret = self.ret;
+        self.place(ret);
+        self.emit(1, &[FP, Opnd::Frame(-1), FP]);
+        self.emit(9, &[Opnd::Frame(-1)]);
+        self.emit(5, &[Opnd::Imm(1), Opnd::Rel(0)]);
+        self.scopes.clear();
+
+        // Slot 0 holds the return address, so the frame has max_slot + 1
+        // cells and slot s is found at [rb + s - frame].
+        let frame = self.max_slot + 1;
+        let mut i = fixups;
+        while i < self.fixups.len() {
+            let (at, fix) = self.fixups[i];
+            match fix {
+                Fix::Slot(s)  => self.code[at] = s - frame,
+                Fix::Frame(f) => self.code[at] = f * frame,
+                _             => {
+                    i += 1;
+                    continue;
+                }
+            }
+            self.fixups.swap_remove(i);
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &'a [Stmt]) -> Result<(), CompileError> {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.temps = 0;
+            self.stmt(stmt)?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt) -> Result<(), CompileError> {
+        let line = stmt.line;
+        match &stmt.kind {
+            StmtKind::Var(name, init) => {
+                let value = match init {
+                    Some(expr) => self.expr(expr, line)?,
+                    None       => Opnd::Imm(0),
+                };
+                if self.scopes.last().unwrap().contains_key(name.as_str()) {
+                    return error(line, format!("{} is defined twice", name));
+                }
+                let slot = self.slots;
+                self.slots   += 1;
+                self.max_slot = self.max_slot.max(slot);
+                self.emit(1, &[value, Opnd::Imm(0), Opnd::Slot(slot)]);
+                self.scopes.last_mut().unwrap().insert(name, slot);
+            }
+            StmtKind::Assign(Expr::Index(name, index), value) => {
+                let value = self.expr(value, line)?;
+                let index = self.expr(index, line)?;
+                let g     = self.array(name, line)?;
+                if let Opnd::Imm(i) = index {
+                    self.emit(1, &[value, Opnd::Imm(0), Opnd::Global(g, i)]);
+                } else {
+                    // The value may live in a slot, which cannot be read
+                    // while the relative base is moved.
+                    self.emit(1, &[value, Opnd::Imm(0), VALUE]);
+                    self.element(g, index, |gen| gen.emit(1, &[VALUE, Opnd::Imm(0), Opnd::Rel(0)]));
+                }
+            }
+            StmtKind::Assign(target, value) => {
+                let value = self.expr(value, line)?;
+                let dst   = self.expr(target, line)?;
+                self.emit(1, &[value, Opnd::Imm(0), dst]);
+            }
+            StmtKind::Expr(expr) => {
+                self.expr(expr, line)?;
+            }
+            StmtKind::If(cond, then, els) => {
+                let (other, end) = (self.label(), self.label());
+                let cond = self.expr(cond, line)?;
+                self.emit(6, &[cond, Opnd::Label(other)]);
+                self.block(then)?;
+                if !els.is_empty() {
+                    self.jump(end);
+                }
+                self.place(other);
+                self.block(els)?;
+                self.place(end);
+            }
+            StmtKind::While(cond, body) => {
+                let (head, end) = (self.label(), self.label());
+                self.place(head);
+                let cond = self.expr(cond, line)?;
+                self.emit(6, &[cond, Opnd::Label(end)]);
+                self.loops.push((head, end));
+                self.block(body)?;
+                self.loops.pop();
+                self.jump(head);
+                self.place(end);
+            }
+            StmtKind::Return(value) => {
+                let value = match value {
+                    Some(expr) => self.expr(expr, line)?,
+                    None       => Opnd::Imm(0),
+                };
+                self.emit(1, &[value, Opnd::Imm(0), Opnd::Slot(1)]);
+                self.jump(self.ret);
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let (head, end) = match self.loops.last() {
+                    Some(&l) => l,
+                    None     => return error(line, String::from("break or continue outside of a loop")),
+                };
+                let brk = matches!(stmt.kind, StmtKind::Break);
+                self.jump(if brk { end } else { head });
+            }
+        }
+        Ok(())
+    }
+
+    fn array(&self, name: &str, line: usize) -> Result<usize, CompileError> {
+        match self.globals.get(name) {
+            Some(&g) if self.program.globals[g].array => Ok(g),
+            Some(_) => error(line, format!("{} is not an array", name)),
+            None    => error(line, format!("undefined array {}", name)),
+        }
+    }
+
+    // Moves the relative base onto element `index` of array `g` for the
+    // instructions emitted by `access`, which must not use slots.
+    fn element(&mut self, g: usize, index: Opnd, access: impl FnOnce(&mut Self)) {
+        self.emit(2, &[FP, Opnd::Imm(-1), OFFSET]);
+        self.emit(1, &[OFFSET, index, OFFSET]);
+        self.emit(1, &[OFFSET, Opnd::Addr(g), OFFSET]);
+        self.emit(9, &[OFFSET]);
+        access(self);
+        self.emit(2, &[OFFSET, Opnd::Imm(-1), OFFSET]);
+        self.emit(9, &[OFFSET]);
+    }
+
+    fn expr(&mut self, expr: &'a Expr, line: usize) -> Result<Opnd, CompileError> {
+        if let Some(n) = const_eval(expr) {
+            return Ok(Opnd::Imm(n));
+        }
+        Ok(match expr {
+            Expr::Num(n) => Opnd::Imm(*n),
+            Expr::Var(name) => {
+                if let Some(&slot) = self.scopes.iter().rev().find_map(|s| s.get(name.as_str())) {
+                    Opnd::Slot(slot)
+                } else {
+                    match self.globals.get(name.as_str()) {
+                        Some(&g) if !self.program.globals[g].array => Opnd::Global(g, 0),
+                        Some(_) => return error(line, format!("array {} needs an index", name)),
+                        None    => return error(line, format!("undefined variable {}", name)),
+                    }
+                }
+            }
+            Expr::Index(name, index) => {
+                let index = self.expr(index, line)?;
+                let g     = self.array(name, line)?;
+                if let Opnd::Imm(i) = index {
+                    Opnd::Global(g, i)
+                } else {
+                    let t = self.temp();
+                    self.element(g, index, |gen| gen.emit(1, &[Opnd::Rel(0), Opnd::Imm(0), VALUE]));
+                    self.emit(1, &[VALUE, Opnd::Imm(0), t]);
+                    t
+                }
+            }
+            Expr::Call(name, args) => self.call(name, args, line)?,
+            Expr::Unary(op, a) => {
+                let a = self.expr(a, line)?;
+                let t = self.temp();
+                match *op {
+                    "-" => self.emit(2, &[a, Opnd::Imm(-1), t]),
+                    _   => self.emit(8, &[a, Opnd::Imm(0), t]),
+                }
+                t
+            }
+            Expr::Binary(op, a, b) => {
+                let a = self.expr(a, line)?;
+                let b = self.expr(b, line)?;
+                let t = self.temp();
+                match *op {
+                    "+"  => self.emit(1, &[a, b, t]),
+                    "-"  => match b {
+                        Opnd::Imm(n) => self.emit(1, &[a, Opnd::Imm(n.wrapping_neg()), t]),
+                        _            => {
+                            self.emit(2, &[b, Opnd::Imm(-1), t]);
+                            self.emit(1, &[a, t, t]);
+                        }
+                    },
+                    "*"  => self.emit(2, &[a, b, t]),
+                    "<"  => self.emit(7, &[a, b, t]),
+                    ">"  => self.emit(7, &[b, a, t]),
+                    "==" => self.emit(8, &[a, b, t]),
+                    "<=" | ">=" | "!=" => {
+                        match *op {
+                            "<=" => self.emit(7, &[b, a, t]),
+                            ">=" => self.emit(7, &[a, b, t]),
+                            _    => self.emit(8, &[a, b, t]),
+                        }
+                        self.emit(8, &[t, Opnd::Imm(0), t]);
+                    }
+                    _ => {
+                        // Both operands are evaluated: a && b is computed as
+                        // (a != 0) * (b != 0), a || b as !((a == 0) * (b == 0)).
+                        let u = self.temp();
+                        self.emit(8, &[a, Opnd::Imm(0), t]);
+                        self.emit(8, &[b, Opnd::Imm(0), u]);
+                        if *op == "&&" {
+                            self.emit(8, &[t, Opnd::Imm(0), t]);
+                            self.emit(8, &[u, Opnd::Imm(0), u]);
+                            self.emit(2, &[t, u, t]);
+                        } else {
+                            self.emit(2, &[t, u, t]);
+                            self.emit(8, &[t, Opnd::Imm(0), t]);
+                        }
+                    }
+                }
+                t
+            }
+        })
+    }
+
+    fn call(&mut self, name: &str, args: &'a [Expr], line: usize) -> Result<Opnd, CompileError> {
+        let arity = match name {
+            "input"  => 0,
+            "output" => 1,
+            _        => match self.funcs.get(name) {
+                Some(&(_, arity)) => arity,
+                None              => return error(line, format!("undefined function {}", name)),
+            },
+        };
+        if args.len() != arity {
+            return error(line, format!("{} takes {} arguments, not {}", name, arity, args.len()));
+        }
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.expr(arg, line)?);
+        }
+        match name {
+            "input" => {
+                let t = self.temp();
+                self.emit(3, &[t]);
+                Ok(t)
+            }
+            "output" => {
+                self.emit(4, &[values[0]]);
+                Ok(Opnd::Imm(0))
+            }
+            _ => {
+                for (i, &value) in values.iter().enumerate() {
+                    self.emit(1, &[value, Opnd::Imm(0), Opnd::Rel(i as i64 + 1)]);
+                }
+                let ret = self.label();
+                self.emit(1, &[Opnd::Imm(0), Opnd::Label(ret), Opnd::Rel(0)]);
+                self.jump(self.funcs[name].0);
+                self.place(ret);
+                let t = self.temp();
+                self.emit(1, &[Opnd::Rel(1), Opnd::Imm(0), t]);
+                Ok(t)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CodeWrites;
+    use crate::IntcodeProg;
+
+    // Compiled programs never write to their code.
+    fn run(src: &str, input: Vec<i64>) -> Vec<i64> {
+        let prog        = compile(src).unwrap_or_else(|e| panic!("{}", e));
+        let mut machine = IntcodeProg::new(&prog);
+        machine.set_code_writes(CodeWrites::Error);
+        machine.run(input).unwrap().into()
+    }
+
+    #[test]
+    fn expressions_and_loops() {
+        let src = "
+            // Outputs the sums 1..n for every n up to the input.
+            fn main() {
+                var n = input();
+                var i = 1;
+                var sum = 0;
+                while (i <= n) {
+                    sum = sum + i;
+                    output(sum);
+                    i = i + 1;
+                }
+                output(-(2 + 3) * 4 - n);
+                output((n > 2) && !(n == 4) || 0);
+                output(n != 3 || n >= 4);
+            }";
+        assert_eq!(run(src, vec![3]), vec![1, 3, 6, -23, 1, 0]);
+        assert_eq!(run(src, vec![0]), vec![-20, 0, 1]);
+    }
+
+    #[test]
+    fn recursion_and_arrays() {
+        let src = "
+            var primes[20];
+            var count;
+            var msg[] = \"ok\";
+
+            fn fib(n) {
+                if (n < 2) { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+
+            // Remainder by repeated subtraction.
+            fn mod(a, b) {
+                while (a >= b) { a = a - b; }
+                return a;
+            }
+
+            fn sieve(limit) {
+                var n = 2;
+                while (n < limit) {
+                    var i = 0;
+                    var prime = 1;
+                    while (i < count) {
+                        if (mod(n, primes[i]) == 0) { prime = 0; break; }
+                        i = i + 1;
+                    }
+                    n = n + 1;
+                    if (!prime) { continue; }
+                    primes[count] = n - 1;
+                    count = count + 1;
+                }
+            }
+
+            fn main() {
+                output(fib(input()));
+                sieve(30);
+                var i = 0;
+                while (i < count) { output(primes[i]); i = i + 1; }
+                i = 0;
+                while (msg[i]) { output(msg[i]); i = i + 1; }
+                output(primes[count - 1] + primes[0]);
+            }";
+        assert_eq!(
+            run(src, vec![10]),
+            vec![55, 2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 'o' as i64, 'k' as i64, 31]
+        );
+    }
+
+    #[test]
+    fn compile_errors() {
+        let err = |src: &str| compile(src).unwrap_err().to_string();
+        assert_eq!(err("fn main() { x = 1; }"), "line 1: undefined variable x");
+        assert_eq!(err("fn f(a) {}\nfn main() { f(); }"), "line 2: f takes 1 arguments, not 0");
+        assert_eq!(err("fn main() {\n  break;\n}"), "line 2: break or continue outside of a loop");
+        assert_eq!(err("fn main() { var a[3]; }"), "line 1: arrays must be global");
+        assert_eq!(err("var a[2] = {1, 2, 3};"), "line 1: too many initializers for a");
+        assert_eq!(err("fn f() {}"), "line 1: missing function main");
+        assert_eq!(err("fn main() { output(1) }"), "line 1: expected ';'");
+        assert_eq!(err("fn main() { output(7 / 2); }"), "line 1: unexpected character '/'");
+        assert_eq!(err("fn input() { return 5; }\nfn main() {}"), "line 1: input is a built-in function");
+        assert_eq!(err("fn main() {\n  var x = 1;\n  var x = 2;\n}"), "line 3: x is defined twice");
+        assert!(compile("fn main() { var x = 1; if (x) { var x = 2; } }").is_ok());
+    }
+
+    #[test]
+    fn decompiles() {
+        // The calling convention is the one the decompiler recognizes.
+        let prog = compile("fn twice(x) { return x + x; }\nfn main() { output(twice(input())); }")
+            .unwrap();
+        let text = crate::decompile::decompile(&prog);
+        assert_eq!(text.matches("fn f").count(), 2);
+        assert!(text.contains("return"));
+    }
+}
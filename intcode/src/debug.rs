@@ -0,0 +1,540 @@
+// Line based debugging protocol for a running Intcode machine.
+//
+// Every command is answered with a single line starting with `ok` or `err`:
+//
+//     step [n]           execute n instructions (default 1)
+//     next [n]           step over calls
+//     finish [n]         run until the current function returns
+//     continue [n]       run until a breakpoint, missing input or halt
+//     backtrace          show the call stack as name@ip, innermost first
+//     break <addr>       set a breakpoint
+//     delete <addr>      remove a breakpoint
+//     breakpoints        list all breakpoints
+//     peek <addr> [n]    read n memory cells (default 1, at most 1024)
+//     poke <addr> <val>  write a memory cell
+//     regs               show ip and rel_base
+//     input <csv>        queue input values
+//     output             take all values output so far
+//     disasm [addr] [n]  show n instructions (default 1) at addr (default
+//                        ip), separated by `; `
+//     scan start         start a memory scan with every cell as candidate
+//     scan <filter>      keep the candidates matching the filter, one of
+//                        changed, unchanged, increased, decreased, output
+//                        (equal to a value in the output queue) or a value
+//     freeze <addr> [v]  keep a cell at v (default its current value)
+//     thaw <addr>        release a frozen cell
+//     detach             close the connection, the machine keeps its state
+//     quit               close the connection and stop serving
+//
+// `next`, `finish` and `continue` execute at most n instructions (default
+// 10 million) and report `limit` when they run out, so that programs which
+// never wait for input cannot block the server.
+//
+// Run states are reported as `running`, `break`, `input` (waiting for
+// input), `halted` or `limit`, followed by the instruction pointer and the
+// name of the symbol at that position, if any. Addresses can be given as numbers or
+// symbol names (`score`, `tiles+12`). Calls are tracked as described in the
+// callstack module; `next` and `finish` stop early at breakpoints. Scans
+// reply with the number of candidates and the first few of them as
+// addr=value, frozen cells are rewritten after every step (see the scanner
+// module).
+
+use std::collections::BTreeSet;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::net::ToSocketAddrs;
+
+use crate::callstack::CallStack;
+use crate::decode_word;
+use crate::disasm;
+use crate::scanner::Filter;
+use crate::scanner::Scanner;
+use crate::symbols::SymbolMap;
+use crate::IntcodeProg;
+use crate::ProgramStatus;
+use crate::Word;
+
+// Instructions executed by `next`, `finish` and `continue` by default.
+pub const MAX_STEPS: usize = 10_000_000;
+
+// Cells `peek` reads at most.
+const PEEK_MAX: usize = 1024;
+
+// Candidates listed in the reply to `scan`.
+const SCAN_SHOWN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Running,
+    Breakpoint,
+    WaitingForInput,
+    Halted,
+    // The step limit was reached.
+    Limit,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Session {
+    Detach,
+    Quit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Debugger<W: Word = i64> {
+    pub prog:        IntcodeProg<W>,
+    pub input:       VecDeque<W>,
+    pub output:      VecDeque<W>,
+    pub breakpoints: BTreeSet<usize>,
+    pub symbols:     SymbolMap,
+    pub calls:       CallStack,
+    pub scanner:     Scanner<W>,
+    halted:          bool,
+}
+
+impl<W: Word> Debugger<W> {
+    pub fn new(prog: IntcodeProg<W>) -> Debugger<W> {
+        Debugger {
+            prog,
+            input:       VecDeque::new(),
+            output:      VecDeque::new(),
+            breakpoints: BTreeSet::new(),
+            symbols:     SymbolMap::default(),
+            calls:       CallStack::new(),
+            scanner:     Scanner::default(),
+            halted:      false,
+        }
+    }
+
+    pub fn step(&mut self) -> Result<Stop, String> {
+        if self.halted {
+            return Ok(Stop::Halted);
+        }
+        let status = self
+            .calls
+            .step(&mut self.prog, &mut self.input, &mut self.output)
+            .map_err(|e| e.to_string())?;
+        self.scanner.apply(&mut self.prog).map_err(|e| e.to_string())?;
+        Ok(match status {
+            ProgramStatus::Success         => Stop::Running,
+            ProgramStatus::WaitingForInput => Stop::WaitingForInput,
+            ProgramStatus::Finished        => {
+                self.halted = true;
+                Stop::Halted
+            }
+        })
+    }
+
+    // Runs until the machine stops or a breakpoint is hit. A breakpoint at
+    // the current position does not prevent the machine from starting.
+    pub fn cont(&mut self, max_steps: usize) -> Result<Stop, String> {
+        self.run_while(max_steps, |_| true)
+    }
+
+    // Executes the current instruction and, if it was a call, runs until
+    // the call returned.
+    pub fn step_over(&mut self, max_steps: usize) -> Result<Stop, String> {
+        let depth = self.calls.depth();
+        self.run_while(max_steps, |calls| calls.depth() > depth)
+    }
+
+    // Runs until the current function returned.
+    pub fn step_out(&mut self, max_steps: usize) -> Result<Stop, String> {
+        let depth = self.calls.depth();
+        if depth == 0 {
+            return Err(String::from("not inside a call"));
+        }
+        self.run_while(max_steps, |calls| calls.depth() >= depth)
+    }
+
+    // Steps at least once and then as long as the condition holds, stopping
+    // early at breakpoints and after `max_steps` instructions.
+    fn run_while<F: Fn(&CallStack) -> bool>(&mut self, max_steps: usize, cond: F) -> Result<Stop, String> {
+        for _ in 0..max_steps.max(1) {
+            match self.step()? {
+                Stop::Running if self.breakpoints.contains(&self.prog.ip()) => {
+                    return Ok(Stop::Breakpoint)
+                }
+                Stop::Running if cond(&self.calls) => (),
+                stop                               => return Ok(stop),
+            }
+        }
+        Ok(Stop::Limit)
+    }
+
+    fn state(&self, stop: Stop) -> String {
+        let state = match stop {
+            Stop::Running         => "running",
+            Stop::Breakpoint      => "break",
+            Stop::WaitingForInput => "input",
+            Stop::Halted          => "halted",
+            Stop::Limit           => "limit",
+        };
+        match self.symbols.name(self.prog.ip()) {
+            Some(name) => format!("{} ip={} {}", state, self.prog.ip(), name),
+            None       => format!("{} ip={}", state, self.prog.ip()),
+        }
+    }
+
+    // Executes a single protocol command and returns the reply.
+    pub fn command(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        let arg = |i: usize| -> Result<&str, String> {
+            words.get(i).copied().ok_or_else(|| String::from("missing argument"))
+        };
+        let num = |i: usize| -> Result<usize, String> {
+            arg(i)?.parse().map_err(|_| format!("invalid number {}", arg(i).unwrap()))
+        };
+        // Addresses have to fit into a word to be peeked and poked.
+        let addr = |i: usize| -> Result<usize, String> {
+            let addr = self.symbols.addr(arg(i)?).filter(|&a| i64::try_from(a).is_ok());
+            addr.ok_or_else(|| format!("invalid address {}", arg(i).unwrap()))
+        };
+        let max_steps = || -> Result<usize, String> {
+            if words.len() > 1 { num(1) } else { Ok(MAX_STEPS) }
+        };
+        let word = |i: usize| -> Result<W, String> {
+            arg(i)?.parse().map_err(|_| format!("invalid value {}", arg(i).unwrap()))
+        };
+
+        match words.first().copied().unwrap_or("") {
+            "step" => {
+                let n        = if words.len() > 1 { num(1)? } else { 1 };
+                let mut stop = Stop::Running;
+                for _ in 0..n {
+                    stop = self.step()?;
+                    if stop != Stop::Running {
+                        break;
+                    }
+                }
+                Ok(self.state(stop))
+            }
+            "next" => {
+                let stop = self.step_over(max_steps()?)?;
+                Ok(self.state(stop))
+            }
+            "finish" => {
+                let stop = self.step_out(max_steps()?)?;
+                Ok(self.state(stop))
+            }
+            "continue" => {
+                let stop = self.cont(max_steps()?)?;
+                Ok(self.state(stop))
+            }
+            "backtrace" => {
+                let trace = self.calls.backtrace(self.prog.ip(), &self.symbols);
+                Ok(join(trace.iter().map(|(ip, name)| format!("{}@{}", name, ip))))
+            }
+            "break" => {
+                self.breakpoints.insert(addr(1)?);
+                Ok(String::new())
+            }
+            "delete" => {
+                if self.breakpoints.remove(&addr(1)?) {
+                    Ok(String::new())
+                } else {
+                    Err(String::from("no such breakpoint"))
+                }
+            }
+            "breakpoints" => Ok(join(self.breakpoints.iter())),
+            "peek" => {
+                let start = addr(1)?;
+                let count = if words.len() > 2 { num(2)? } else { 1 };
+                if count > PEEK_MAX {
+                    return Err(format!("at most {} cells can be read at once", PEEK_MAX));
+                }
+                let end   = start.checked_add(count).ok_or_else(|| String::from("invalid range"))?;
+                Ok(join(self.prog.dump_range(start..end).into_iter()))
+            }
+            "poke" => {
+                let (addr, val) = (addr(1)?, word(2)?);
+                self.prog.poke(addr as i64, val).map_err(|e| e.to_string())?;
+                Ok(String::new())
+            }
+            "regs" => Ok(format!("ip={} rel_base={}", self.prog.ip(), self.prog.rel_base())),
+            "input" => {
+                for s in arg(1)?.split(',') {
+                    let val = s.parse().map_err(|_| format!("invalid value {}", s))?;
+                    self.input.push_back(val);
+                }
+                Ok(String::new())
+            }
+            "output" => Ok(join(self.output.drain(..))),
+            "disasm" => {
+                let mut at = if words.len() > 1 { addr(1)? } else { self.prog.ip() };
+                let count  = if words.len() > 2 { num(2)? } else { 1 };
+                if count > PEEK_MAX {
+                    return Err(format!("at most {} instructions can be shown at once", PEEK_MAX));
+                }
+                let mut lines = Vec::new();
+                for _ in 0..count {
+                    let end = at.checked_add(4).ok_or_else(|| format!("invalid address {}", at))?;
+                    let mem: Vec<_> = self.prog.dump_range(at..end).into_iter().map(decode_word).collect();
+                    match disasm::decode(&mem, 0) {
+                        Some(instr) => {
+                            lines.push(format!("{}: {}", at, instr.symbolized(&self.symbols)));
+                            at += instr.next();
+                        }
+                        None if lines.is_empty() => return Err(format!("no valid instruction at {}", at)),
+                        None                     => break,
+                    }
+                }
+                Ok(lines.join("; "))
+            }
+            "scan" if words.get(1) == Some(&"start") => {
+                self.scanner.start(&self.prog);
+                Ok(self.scanner.candidates().len().to_string())
+            }
+            "scan" => {
+                let filter = match arg(1)? {
+                    "changed"   => Filter::Changed,
+                    "unchanged" => Filter::Unchanged,
+                    "increased" => Filter::Increased,
+                    "decreased" => Filter::Decreased,
+                    "output"    => Filter::OneOf(self.output.iter().copied().collect()),
+                    _           => Filter::Equals(word(1)?),
+                };
+                let count = self.scanner.filter(&self.prog, &filter);
+                let mut shown = vec![count.to_string()];
+                for &addr in self.scanner.candidates().iter().take(SCAN_SHOWN) {
+                    let val = self.prog.peek(addr as i64).map_err(|e| e.to_string())?;
+                    shown.push(format!("{}={}", addr, val));
+                }
+                Ok(shown.join(" "))
+            }
+            "freeze" => {
+                let addr = addr(1)?;
+                let val  = match words.len() {
+                    2 => self.prog.peek(addr as i64).map_err(|e| e.to_string())?,
+                    _ => word(2)?,
+                };
+                self.scanner.freeze(addr, val);
+                self.scanner.apply(&mut self.prog).map_err(|e| e.to_string())?;
+                Ok(String::new())
+            }
+            "thaw" => {
+                if self.scanner.thaw(addr(1)?) {
+                    Ok(String::new())
+                } else {
+                    Err(String::from("cell is not frozen"))
+                }
+            }
+            cmd => Err(format!("unknown command {}", cmd)),
+        }
+    }
+
+    // Serves a single connection until it is closed, detached or quit.
+    pub fn serve<S: io::Read + io::Write>(&mut self, stream: S) -> io::Result<Session> {
+        let mut reader = BufReader::new(stream);
+        let mut line   = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(Session::Detach);
+            }
+            let session = match line.trim() {
+                "detach" => Some(Session::Detach),
+                "quit"   => Some(Session::Quit),
+                _        => None,
+            };
+            let reply = match session {
+                Some(_) => String::from("ok"),
+                None    => match self.command(&line) {
+                    Ok(msg) if msg.is_empty() => String::from("ok"),
+                    Ok(msg)                   => format!("ok {}", msg),
+                    Err(msg)                  => format!("err {}", msg),
+                },
+            };
+            let stream = reader.get_mut();
+            writeln!(stream, "{}", reply)?;
+            stream.flush()?;
+            if let Some(session) = session {
+                return Ok(session);
+            }
+        }
+    }
+
+    // Accepts connections one after the other until a client quits.
+    pub fn listen_tcp<A: ToSocketAddrs>(&mut self, addr: A) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        self.accept_tcp(&listener)
+    }
+
+    pub fn accept_tcp(&mut self, listener: &TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            if self.serve(stream?)? == Session::Quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<std::path::Path>>(&mut self, path: P) -> io::Result<()> {
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        for stream in listener.incoming() {
+            if self.serve(stream?)? == Session::Quit {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn join<T: ToString>(values: impl Iterator<Item = T>) -> String {
+    values.map(|v| v.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::thread;
+
+    // Outputs twice its input, forever.
+    const DOUBLER: [i64; 9] = [3, 20, 1002, 20, 2, 20, 4, 20, 1105];
+
+    fn doubler() -> Debugger {
+        let mut prog = DOUBLER.to_vec();
+        prog.extend_from_slice(&[1, 0]);
+        Debugger::new(IntcodeProg::new(&prog))
+    }
+
+    #[test]
+    fn commands() {
+        let mut dbg = doubler();
+        assert_eq!(dbg.command("disasm"), Ok(String::from("0: in [20]")));
+        assert_eq!(dbg.command("disasm 0 2"), Ok(String::from("0: in [20]; 2: mul [20], 2, [20]")));
+        assert_eq!(dbg.command("disasm 20"), Err(String::from("no valid instruction at 20")));
+        let huge = "9223372036854775808";
+        assert_eq!(dbg.command(&format!("disasm {}", huge)), Err(format!("invalid address {}", huge)));
+        assert!(dbg.command("disasm 0 -1").is_err());
+        assert_eq!(dbg.command("step"), Ok(String::from("input ip=0")));
+        assert_eq!(dbg.command("input 21,4"), Ok(String::new()));
+        assert_eq!(dbg.command("break 6"), Ok(String::new()));
+        assert_eq!(dbg.command("continue"), Ok(String::from("break ip=6")));
+        assert_eq!(dbg.command("peek 20"), Ok(String::from("42")));
+        assert_eq!(dbg.command("poke 20 -1"), Ok(String::new()));
+        assert_eq!(dbg.command("continue"), Ok(String::from("break ip=6")));
+        assert_eq!(dbg.command("output"), Ok(String::from("-1")));
+        assert_eq!(dbg.command("delete 6"), Ok(String::new()));
+        assert_eq!(dbg.command("continue"), Ok(String::from("input ip=0")));
+        assert_eq!(dbg.command("output"), Ok(String::from("8")));
+        assert_eq!(dbg.command("step 2"), Ok(String::from("input ip=0")));
+        assert_eq!(dbg.command("regs"), Ok(String::from("ip=0 rel_base=0")));
+        assert_eq!(dbg.command("peek 19 3"), Ok(String::from("0 8 0")));
+        assert!(dbg.command("delete 6").is_err());
+        assert!(dbg.command("poke 1").is_err());
+        assert!(dbg.command("jump 3").is_err());
+    }
+
+    #[test]
+    fn symbols() {
+        let mut dbg = doubler();
+        dbg.symbols = SymbolMap::parse("0 loop code\n6 print code\n20 value").unwrap();
+        assert_eq!(dbg.command("disasm"), Ok(String::from("0: in [value]")));
+        assert_eq!(dbg.command("input 3"), Ok(String::new()));
+        assert_eq!(dbg.command("break print"), Ok(String::new()));
+        assert_eq!(dbg.command("continue"), Ok(String::from("break ip=6 print")));
+        assert_eq!(dbg.command("peek value"), Ok(String::from("6")));
+        assert_eq!(dbg.command("poke value 7"), Ok(String::new()));
+        assert_eq!(dbg.command("peek value+1"), Ok(String::from("0")));
+        assert_eq!(dbg.command("continue"), Ok(String::from("input ip=0 loop")));
+        assert_eq!(dbg.command("output"), Ok(String::from("7")));
+        assert!(dbg.command("break nowhere").is_err());
+    }
+
+    #[test]
+    fn calls() {
+        let prog = crate::compiler::compile(
+            "fn twice(x) { return x + x; }
+             fn main() { var a = twice(input()); output(twice(a)); }",
+        )
+        .unwrap();
+        let mut dbg = Debugger::new(IntcodeProg::new(&prog));
+        dbg.symbols = SymbolMap::parse("10 twice code").unwrap();
+        assert_eq!(dbg.command("input 5"), Ok(String::new()));
+        assert_eq!(dbg.command("break twice"), Ok(String::new()));
+        assert_eq!(dbg.command("continue"), Ok(String::from("break ip=10 twice")));
+        assert_eq!(dbg.command("backtrace"), Ok(String::from("twice@10 f40@56 main@6")));
+        assert_eq!(dbg.command("finish"), Ok(String::from("running ip=59")));
+        assert_eq!(dbg.command("delete twice"), Ok(String::new()));
+        assert_eq!(dbg.command("step 3"), Ok(String::from("running ip=71")));
+        assert_eq!(dbg.command("next"), Ok(String::from("running ip=75")));
+        assert_eq!(dbg.command("next"), Ok(String::from("running ip=78")));
+        assert_eq!(dbg.command("finish"), Ok(String::from("running ip=9")));
+        assert_eq!(dbg.command("output"), Ok(String::from("20")));
+        assert!(dbg.command("finish").is_err());
+    }
+
+    #[test]
+    fn limits() {
+        let mut dbg = doubler();
+        assert!(dbg.command("peek 5 18446744073709551615").is_err());
+        assert!(dbg.command("peek 18446744073709551615 1").is_err());
+        assert!(dbg.command("peek 5 1025").is_err());
+        assert_eq!(dbg.command("peek 5 1024").map(|s| s.split(' ').count()), Ok(1024));
+
+        // jnz 1, 0 never stops on its own.
+        let mut dbg = Debugger::new(IntcodeProg::new(&[1105, 1, 0]));
+        assert_eq!(dbg.command("continue 1000"), Ok(String::from("limit ip=0")));
+    }
+
+    #[test]
+    fn scan() {
+        let mut prog = DOUBLER.to_vec();
+        prog.extend_from_slice(&[1, 0]);
+        prog.resize(21, 0);
+        let mut dbg = Debugger::new(IntcodeProg::new(&prog));
+        assert_eq!(dbg.command("scan start"), Ok(String::from("21")));
+        assert_eq!(dbg.command("input 5,6,7"), Ok(String::new()));
+        assert_eq!(dbg.command("step 4"), Ok(String::from("running ip=0")));
+        assert_eq!(dbg.command("scan changed"), Ok(String::from("1 20=10")));
+        assert_eq!(dbg.command("step 4"), Ok(String::from("running ip=0")));
+        assert_eq!(dbg.command("scan output"), Ok(String::from("1 20=12")));
+        assert_eq!(dbg.command("scan 13"), Ok(String::from("0")));
+        assert_eq!(dbg.command("freeze 20 1"), Ok(String::new()));
+        assert_eq!(dbg.command("step 4"), Ok(String::from("running ip=0")));
+        assert_eq!(dbg.command("output"), Ok(String::from("10 12 1")));
+        assert_eq!(dbg.command("thaw 20"), Ok(String::new()));
+        assert!(dbg.command("thaw 20").is_err());
+        assert!(dbg.command("scan").is_err());
+        let huge = "9223372036854775808";
+        assert_eq!(dbg.command(&format!("freeze {}", huge)), Err(format!("invalid address {}", huge)));
+        assert!(dbg.command(&format!("peek {}", huge)).is_err());
+    }
+
+    #[test]
+    fn tcp_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr     = listener.local_addr().unwrap();
+        let server   = thread::spawn(move || {
+            let mut dbg = doubler();
+            dbg.accept_tcp(&listener).unwrap();
+            dbg
+        });
+
+        let session = |commands: &[&str]| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            commands
+                .iter()
+                .map(|cmd| {
+                    writeln!(stream, "{}", cmd).unwrap();
+                    let mut reply = String::new();
+                    reader.read_line(&mut reply).unwrap();
+                    reply.trim_end().to_string()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(session(&["input 5", "continue", "detach"]), vec!["ok", "ok input ip=0", "ok"]);
+        assert_eq!(session(&["output", "foo", "quit"]), vec!["ok 10", "err unknown command foo", "ok"]);
+
+        let dbg = server.join().unwrap();
+        assert_eq!(dbg.prog.ip(), 0);
+    }
+}
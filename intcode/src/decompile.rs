@@ -0,0 +1,629 @@
+// Decompiles Intcode programs into structured pseudocode.
+//
+// Each function of the control-flow graph is structured into `if`/`else`
+// and `while` statements (falling back to `goto` for anything irreducible).
+// Relative base frames are turned into named slots: `a1`, `a2`, ... for
+// arguments, `l3`, ... for locals and `o1`, `o2`, ... for the arguments of
+// outgoing calls. Memory cells are named `t<addr>` if they are only used
+// within a single function and `g<addr>` otherwise. Values that are
+// computed into a cell only to be consumed by the next instruction are
+// inlined into that instruction. Names from a symbol map take precedence.
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::cfg::Cfg;
+use crate::cfg::Exit;
+use crate::cfg::Function;
+use crate::disasm::Instr;
+use crate::disasm::Mode;
+use crate::disasm::Op;
+use crate::disasm::Param;
+use crate::symbols::SymbolMap;
+
+pub fn decompile(mem: &[i64]) -> String {
+    decompile_with(mem, &SymbolMap::default())
+}
+
+pub fn decompile_with(mem: &[i64], symbols: &SymbolMap) -> String {
+    let cfg       = Cfg::build(mem);
+    let functions = cfg.functions();
+
+    // Which functions access which memory cells, and how often a cell is
+    // read by position mode parameters in total.
+    let mut users = HashMap::<i64, HashSet<usize>>::new();
+    let mut reads = HashMap::<i64, usize>::new();
+    for func in functions.iter() {
+        for instr in func.blocks.iter().flat_map(|b| cfg.blocks[b].instrs.iter()) {
+            for (i, p) in instr.params.iter().enumerate() {
+                if p.mode == Mode::Position {
+                    users.entry(p.value).or_default().insert(func.entry);
+                    if Some(i) != instr.op.write_param() {
+                        *reads.entry(p.value).or_default() += 1;
+                    }
+                }
+            }
+        }
+    }
+    let globals = users.into_iter().filter(|(_, u)| u.len() > 1).map(|(a, _)| a).collect();
+
+    let mut out = String::new();
+    for func in functions.iter() {
+        let mut dec = Decompiler::new(&cfg, func, &globals, &reads, symbols);
+        out += &dec.run();
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Atom(String),
+    Bin(String, &'static str, String),
+}
+
+impl Expr {
+    fn text(&self) -> String {
+        match self {
+            Expr::Atom(a)      => a.clone(),
+            Expr::Bin(a, o, b) => format!("{} {} {}", a, o, b),
+        }
+    }
+
+    fn paren(&self) -> String {
+        match self {
+            Expr::Atom(a) => a.clone(),
+            _             => format!("({})", self.text()),
+        }
+    }
+
+    fn truthy(&self) -> String {
+        match self {
+            Expr::Atom(a)                               => a.clone(),
+            Expr::Bin(_, o, _) if *o == "<" || *o == "==" => self.text(),
+            _                                           => format!("{} != 0", self.text()),
+        }
+    }
+
+    fn falsy(&self) -> String {
+        match self {
+            Expr::Bin(a, "<", b)  => format!("{} >= {}", a, b),
+            Expr::Bin(a, "==", b) => format!("{} != {}", a, b),
+            _                     => format!("{} == 0", self.text()),
+        }
+    }
+}
+
+struct Line {
+    indent: usize,
+    text:   String,
+    // Set for the first line of a block, used to place `goto` labels.
+    block:  Option<usize>,
+}
+
+struct Decompiler<'a> {
+    cfg:     &'a Cfg,
+    func:    &'a Function,
+    globals: &'a HashSet<i64>,
+    reads:   &'a HashMap<i64, usize>,
+    symbols: &'a SymbolMap,
+    args:    BTreeSet<i64>,
+    // Writer instructions inlined into the instruction that follows them.
+    inlined: HashMap<usize, Instr>,
+    ipdom:   HashMap<usize, usize>,
+    loops:   HashMap<usize, BTreeSet<usize>>,
+    follow:  HashMap<usize, usize>,
+    // Innermost loop header and its follow block (where `break` goes).
+    ctx:     Vec<(usize, Option<usize>)>,
+    emitted: HashSet<usize>,
+    gotos:   HashSet<usize>,
+    lines:   Vec<Line>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(
+        cfg:     &'a Cfg,
+        func:    &'a Function,
+        globals: &'a HashSet<i64>,
+        reads:   &'a HashMap<i64, usize>,
+        symbols: &'a SymbolMap,
+    ) -> Decompiler<'a> {
+        let mut dec = Decompiler {
+            cfg,
+            func,
+            globals,
+            reads,
+            symbols,
+            args:    BTreeSet::new(),
+            inlined: HashMap::new(),
+            ipdom:   cfg.post_dominators(func),
+            loops:   HashMap::new(),
+            follow:  HashMap::new(),
+            ctx:     Vec::new(),
+            emitted: HashSet::new(),
+            gotos:   HashSet::new(),
+            lines:   Vec::new(),
+        };
+        dec.find_args();
+        dec.find_inlined();
+        dec.find_loops();
+        dec
+    }
+
+    fn succs(&self, b: usize) -> Vec<usize> {
+        self.cfg.blocks[&b]
+            .successors()
+            .into_iter()
+            .filter(|s| self.func.blocks.contains(s))
+            .collect()
+    }
+
+    fn instrs(&self) -> impl Iterator<Item = &'a Instr> + 'a {
+        let cfg = self.cfg;
+        self.func.blocks.iter().flat_map(move |b| cfg.blocks[b].instrs.iter())
+    }
+
+    // Frame slots that are read before they are written (in address order)
+    // are considered to be arguments.
+    fn find_args(&mut self) {
+        let frame = match self.func.frame {
+            Some(frame) => frame,
+            None        => return,
+        };
+        let mut written = HashSet::new();
+        for instr in self.instrs() {
+            for (i, p) in instr.params.iter().enumerate() {
+                let slot = p.value + frame;
+                if p.mode != Mode::Relative || slot < 1 || slot >= frame {
+                    continue;
+                }
+                if Some(i) == instr.op.write_param() {
+                    written.insert(slot);
+                } else if !written.contains(&slot) {
+                    self.args.insert(slot);
+                }
+            }
+        }
+    }
+
+    fn find_inlined(&mut self) {
+        for b in self.func.blocks.iter() {
+            for pair in self.cfg.blocks[b].instrs.windows(2) {
+                let (writer, reader) = (&pair[0], &pair[1]);
+                let dst = match writer.op {
+                    Op::Add | Op::Mul | Op::Lt | Op::Eq => writer.params[2],
+                    _                                   => continue,
+                };
+                let read_by_next = reader
+                    .params
+                    .iter()
+                    .enumerate()
+                    .any(|(i, p)| *p == dst && Some(i) != reader.op.write_param());
+                if dst.mode == Mode::Position
+                    && read_by_next
+                    && self.reads.get(&dst.value) == Some(&1)
+                {
+                    self.inlined.insert(reader.addr, writer.clone());
+                }
+            }
+        }
+    }
+
+    fn find_loops(&mut self) {
+        // Depth-first search for back edges.
+        let mut back_edges = Vec::new();
+        let mut on_stack   = HashSet::new();
+        let mut visited    = HashSet::new();
+        let mut stack      = vec![(self.func.entry, 0)];
+        visited.insert(self.func.entry);
+        on_stack.insert(self.func.entry);
+        while let Some(&mut (b, ref mut i)) = stack.last_mut() {
+            let succs = self.succs(b);
+            if *i < succs.len() {
+                let s = succs[*i];
+                *i += 1;
+                if on_stack.contains(&s) {
+                    back_edges.push((b, s));
+                } else if visited.insert(s) {
+                    on_stack.insert(s);
+                    stack.push((s, 0));
+                }
+            } else {
+                on_stack.remove(&b);
+                stack.pop();
+            }
+        }
+
+        // Natural loop of each header: everything reaching a back edge
+        // source without passing through the header.
+        let mut preds = HashMap::<usize, Vec<usize>>::new();
+        for &b in self.func.blocks.iter() {
+            for s in self.succs(b) {
+                preds.entry(s).or_default().push(b);
+            }
+        }
+        for (src, header) in back_edges {
+            let body = self.loops.entry(header).or_insert_with(|| {
+                std::iter::once(header).collect()
+            });
+            let mut todo = vec![src];
+            while let Some(b) = todo.pop() {
+                if body.insert(b) {
+                    todo.extend(preds.get(&b).into_iter().flatten());
+                }
+            }
+        }
+
+        for (&header, body) in self.loops.iter() {
+            let exits: BTreeSet<_> = body
+                .iter()
+                .flat_map(|&b| self.succs(b))
+                .filter(|s| !body.contains(s))
+                .collect();
+            let from_header = self.succs(header).into_iter().find(|s| !body.contains(s));
+            if let Some(f) = from_header.or_else(|| exits.iter().next().copied()) {
+                self.follow.insert(header, f);
+            }
+        }
+    }
+
+    fn run(&mut self) -> String {
+        self.emit_seq(self.func.entry, None, 1);
+        // Blocks that could only be reached through a `goto` from within a
+        // loop are appended at the end of the function.
+        while let Some(&b) = self.gotos.iter().filter(|b| !self.emitted.contains(b)).min() {
+            if !self.func.blocks.contains(&b) {
+                self.emitted.insert(b);
+                continue;
+            }
+            self.emit_seq(b, None, 1);
+        }
+
+        let name = self.func_name(self.func.entry);
+        let args: Vec<_> = self.args.iter().map(|s| format!("a{}", s)).collect();
+        let mut out = format!("fn {}({}) {{\n", name, args.join(", "));
+        for line in self.lines.iter() {
+            if let Some(b) = line.block.filter(|b| self.gotos.contains(b)) {
+                out += &format!("{}L{}:\n", "    ".repeat(line.indent - 1), b);
+            }
+            if !line.text.is_empty() {
+                out += &format!("{}{}\n", "    ".repeat(line.indent), line.text);
+            }
+        }
+        out + "}\n"
+    }
+
+    fn func_name(&self, entry: usize) -> String {
+        if let Some(sym) = self.symbols.get(entry) {
+            sym.name.clone()
+        } else if entry == 0 {
+            String::from("main")
+        } else {
+            format!("f{}", entry)
+        }
+    }
+
+    fn line(&mut self, indent: usize, text: String) {
+        self.lines.push(Line { indent, text, block: None });
+    }
+
+    fn goto(&mut self, indent: usize, b: usize) {
+        self.gotos.insert(b);
+        self.line(indent, format!("goto L{}", b));
+    }
+
+    fn emit_seq(&mut self, mut b: usize, stop: Option<usize>, indent: usize) {
+        loop {
+            if Some(b) == stop {
+                return;
+            } else if !self.cfg.blocks.contains_key(&b) {
+                return self.line(indent, format!("goto {} // not decodable", b));
+            }
+            if let Some(&(header, follow)) = self.ctx.last() {
+                if b == header {
+                    return self.line(indent, String::from("continue"));
+                } else if Some(b) == follow {
+                    return self.line(indent, String::from("break"));
+                } else if !self.loops[&header].contains(&b) {
+                    return self.goto(indent, b);
+                }
+            }
+            if self.emitted.contains(&b) || !self.func.blocks.contains(&b) {
+                return self.goto(indent, b);
+            }
+            let next = if self.loops.contains_key(&b) {
+                self.emit_loop(b, indent)
+            } else {
+                self.emit_block(b, indent)
+            };
+            match next {
+                Some(next) => b = next,
+                None       => return,
+            }
+        }
+    }
+
+    fn emit_loop(&mut self, header: usize, indent: usize) -> Option<usize> {
+        let follow = self.follow.get(&header).copied();
+        let block  = &self.cfg.blocks[&header];
+        self.ctx.push((header, follow));
+        let only_cond = block.instrs.iter().all(|i| {
+            i.is_jump() || self.inlined.values().any(|w| w.addr == i.addr)
+        });
+        match block.exit {
+            Exit::Branch { target, next }
+                if only_cond && (Some(target) == follow || Some(next) == follow) =>
+            {
+                // Plain `while` loop, the header only evaluates the condition.
+                let (body, taken) = if Some(next) == follow { (target, true) } else { (next, false) };
+                let cond = self.cond(header, taken);
+                self.emitted.insert(header);
+                self.lines.push(Line {
+                    indent,
+                    text:  format!("while ({}) {{", cond),
+                    block: Some(header),
+                });
+                self.emit_seq(body, Some(header), indent + 1);
+            }
+            _ => {
+                self.line(indent, String::from("while (true) {"));
+                if let Some(next) = self.emit_block(header, indent + 1) {
+                    self.emit_seq(next, Some(header), indent + 1);
+                }
+            }
+        }
+        self.ctx.pop();
+        self.line(indent, String::from("}"));
+        follow
+    }
+
+    fn emit_block(&mut self, b: usize, indent: usize) -> Option<usize> {
+        self.emitted.insert(b);
+        let block = &self.cfg.blocks[&b];
+        let first = self.lines.len();
+
+        // Instructions that are part of the calling convention or consumed
+        // by other statements are not printed on their own.
+        let mut skip: HashSet<_> = self.inlined.values().map(|i| i.addr).collect();
+        let mut call_args = Vec::new();
+        if let Some(jump) = block.jump() {
+            skip.insert(jump.addr);
+        }
+        if let Some(frame) = self.func.frame {
+            for instr in block.instrs.iter() {
+                let arb = instr.op == Op::Arb && instr.params[0].mode == Mode::Immediate;
+                if arb && (instr.params[0].value == frame && b == self.func.entry
+                    || instr.params[0].value == -frame)
+                {
+                    skip.insert(instr.addr);
+                }
+            }
+        }
+        if let Exit::Call { .. } | Exit::IndirectCall { .. } = block.exit {
+            let n = block.instrs.len();
+            skip.insert(block.instrs[n - 2].addr);
+            for instr in block.instrs[..n - 2].iter().rev() {
+                match instr.op.write_param().map(|w| instr.params[w]) {
+                    Some(Param { mode: Mode::Relative, value })
+                        if value > 0 && instr.op != Op::In
+                            && call_args.iter().all(|(v, _)| *v != value) =>
+                    {
+                        skip.insert(instr.addr);
+                        call_args.push((value, self.rhs(instr).text()));
+                    }
+                    _ => break,
+                }
+            }
+            call_args.sort();
+        }
+
+        for instr in block.instrs.iter().filter(|i| !skip.contains(&i.addr)) {
+            let text = self.statement(instr);
+            self.line(indent, text);
+        }
+
+        let next = match block.exit.clone() {
+            Exit::Goto(next) => Some(next),
+            Exit::Halt => None,
+            Exit::Return => {
+                self.line(indent, String::from("return"));
+                None
+            }
+            Exit::Indirect { next } => {
+                let target = self.operand(block.jump().unwrap(), 1);
+                self.line(indent, format!("goto *{}", target));
+                next
+            }
+            Exit::Invalid => {
+                self.line(indent, String::from("// runs into data"));
+                None
+            }
+            Exit::Call { target, ret } => {
+                let args: Vec<_> = call_args.into_iter().map(|(_, a)| a).collect();
+                self.line(indent, format!("{}({})", self.func_name(target), args.join(", ")));
+                Some(ret)
+            }
+            Exit::IndirectCall { ret } => {
+                let args: Vec<_> = call_args.into_iter().map(|(_, a)| a).collect();
+                let target = self.operand(block.jump().unwrap(), 1);
+                self.line(indent, format!("(*{})({})", target, args.join(", ")));
+                Some(ret)
+            }
+            Exit::Branch { target, next } => self.emit_branch(b, target, next, indent),
+        };
+        if self.lines.len() == first {
+            // Keep an (invisible) line around to attach a label to.
+            self.line(indent, String::new());
+        }
+        self.lines[first].block = Some(b);
+        next
+    }
+
+    fn emit_branch(&mut self, b: usize, target: usize, next: usize, indent: usize) -> Option<usize> {
+        if let Some(&(header, follow)) = self.ctx.last() {
+            for &(succ, other, taken) in [(target, next, true), (next, target, false)].iter() {
+                let keyword = if Some(succ) == follow {
+                    "break"
+                } else if succ == header {
+                    "continue"
+                } else {
+                    continue;
+                };
+                let cond = self.cond(b, taken);
+                self.line(indent, format!("if ({}) {}", cond, keyword));
+                return Some(other);
+            }
+        }
+
+        let join = self.ipdom.get(&b).copied();
+        if Some(target) == join || Some(next) == join {
+            let (body, taken) = if Some(target) == join { (next, false) } else { (target, true) };
+            let cond = self.cond(b, taken);
+            self.line(indent, format!("if ({}) {{", cond));
+            self.emit_seq(body, join, indent + 1);
+        } else {
+            let cond = self.cond(b, true);
+            self.line(indent, format!("if ({}) {{", cond));
+            self.emit_seq(target, join, indent + 1);
+            self.line(indent, String::from("} else {"));
+            self.emit_seq(next, join, indent + 1);
+        }
+        self.line(indent, String::from("}"));
+        join
+    }
+
+    // Condition under which the branch at the end of the block is taken
+    // (or not taken).
+    fn cond(&self, b: usize, taken: bool) -> String {
+        let jump = self.cfg.blocks[&b].jump().unwrap();
+        let expr = match self.inlined.get(&jump.addr) {
+            Some(writer) if writer.params[2] == jump.params[0] => self.rhs(writer),
+            _ => Expr::Atom(self.operand(jump, 0)),
+        };
+        if taken == (jump.op == Op::Jnz) {
+            expr.truthy()
+        } else {
+            expr.falsy()
+        }
+    }
+
+    fn statement(&self, instr: &Instr) -> String {
+        match instr.op {
+            Op::Out  => format!("output({})", self.operand(instr, 0)),
+            Op::Arb  => format!("rb += {}", self.operand(instr, 0)),
+            Op::Halt => String::from("halt"),
+            Op::Jnz | Op::Jz => {
+                // Only reached for jumps in the middle of a block, which
+                // never happens for blocks built by the CFG.
+                format!("// {}", instr)
+            }
+            _ => {
+                let dst = instr.params[instr.op.write_param().unwrap()];
+                format!("{} = {}", self.name(dst), self.rhs(instr).text())
+            }
+        }
+    }
+
+    fn rhs(&self, instr: &Instr) -> Expr {
+        let imm = |i: usize| instr.params[i].imm();
+        let a   = || self.operand(instr, 0);
+        let b   = || self.operand(instr, 1);
+        match instr.op {
+            Op::In                        => Expr::Atom(String::from("input()")),
+            Op::Add if imm(1) == Some(0)  => Expr::Atom(a()),
+            Op::Add if imm(0) == Some(0)  => Expr::Atom(b()),
+            Op::Add if imm(1).is_some_and(|v| v < 0) => {
+                Expr::Bin(a(), "-", (-imm(1).unwrap()).to_string())
+            }
+            Op::Add                       => Expr::Bin(a(), "+", b()),
+            Op::Mul if imm(1) == Some(1)  => Expr::Atom(a()),
+            Op::Mul if imm(0) == Some(1)  => Expr::Atom(b()),
+            Op::Mul if imm(1) == Some(-1) => Expr::Atom(format!("-{}", a())),
+            Op::Mul                       => Expr::Bin(a(), "*", b()),
+            Op::Lt                        => Expr::Bin(a(), "<", b()),
+            Op::Eq                        => Expr::Bin(a(), "==", b()),
+            _                             => Expr::Atom(format!("{}", instr)),
+        }
+    }
+
+    // Read parameter, with the value of an inlined writer substituted.
+    fn operand(&self, instr: &Instr, i: usize) -> String {
+        let param = instr.params[i];
+        match self.inlined.get(&instr.addr) {
+            Some(writer) if writer.params[2] == param => self.rhs(writer).paren(),
+            _                                          => self.name(param),
+        }
+    }
+
+    fn name(&self, param: Param) -> String {
+        if param.mode == Mode::Position && param.value >= 0 {
+            if let Some(name) = self.symbols.name(param.value as usize) {
+                return name;
+            }
+        }
+        match param.mode {
+            Mode::Immediate => param.value.to_string(),
+            Mode::Position if self.globals.contains(&param.value) => format!("g{}", param.value),
+            Mode::Position => format!("t{}", param.value),
+            Mode::Relative => {
+                let slot = self.func.frame.map(|frame| param.value + frame);
+                match slot {
+                    _ if param.value > 0              => format!("o{}", param.value),
+                    Some(0)                           => String::from("ret"),
+                    Some(s) if s > 0 && self.args.contains(&s) => format!("a{}", s),
+                    Some(s) if s > 0                  => format!("l{}", s),
+                    _                                 => format!("rb[{}]", param.value),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn while_loop() {
+        // Counts down from the input and outputs every value.
+        let mem = vec![3, 20, 1007, 20, 1, 21, 1005, 21, 18, 4, 20, 1001, 20, -1, 20, 1105, 1, 2, 99];
+        let expected = "fn main() {\n\
+                        \x20   t20 = input()\n\
+                        \x20   while (t20 >= 1) {\n\
+                        \x20       output(t20)\n\
+                        \x20       t20 = t20 - 1\n\
+                        \x20   }\n\
+                        \x20   halt\n\
+                        }\n";
+        assert_eq!(decompile(&mem), expected);
+
+        let symbols = SymbolMap::parse("0 countdown code\n20 counter").unwrap();
+        let output  = decompile_with(&mem, &symbols);
+        assert!(output.starts_with("fn countdown() {\n    counter = input()\n"));
+        assert!(output.contains("counter = counter - 1"));
+    }
+
+    #[test]
+    fn functions_and_branches() {
+        // Outputs the maximum of two inputs, computed by a function.
+        let mem = vec![
+            109, 100, 203, 1, 203, 2, 21101, 0, 13, 0, 1105, 1, 16, 204, 1, 99, 109, 3, 2207, -2,
+            -1, 40, 1006, 40, 29, 21201, -1, 0, -2, 109, -3, 2105, 1, 0,
+        ];
+        let expected = "fn main() {\n\
+                        \x20   rb += 100\n\
+                        \x20   o1 = input()\n\
+                        \x20   o2 = input()\n\
+                        \x20   f16()\n\
+                        \x20   output(o1)\n\
+                        \x20   halt\n\
+                        }\n\
+                        fn f16(a1, a2) {\n\
+                        \x20   if (a1 < a2) {\n\
+                        \x20       a1 = a2\n\
+                        \x20   }\n\
+                        \x20   return\n\
+                        }\n";
+        assert_eq!(decompile(&mem), expected);
+    }
+}
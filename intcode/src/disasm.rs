@@ -0,0 +1,357 @@
+// Static decoding of Intcode programs.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::symbols::SymbolMap;
+use crate::symbols::SymbolType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Mul,
+    In,
+    Out,
+    Jnz,
+    Jz,
+    Lt,
+    Eq,
+    Arb,
+    Halt,
+}
+
+impl Op {
+    pub fn from_code(op_code: i64) -> Option<Op> {
+        Some(match op_code {
+            1  => Op::Add,
+            2  => Op::Mul,
+            3  => Op::In,
+            4  => Op::Out,
+            5  => Op::Jnz,
+            6  => Op::Jz,
+            7  => Op::Lt,
+            8  => Op::Eq,
+            9  => Op::Arb,
+            99 => Op::Halt,
+            _  => return None,
+        })
+    }
+
+    pub fn arity(self) -> usize {
+        match self {
+            Op::Add | Op::Mul | Op::Lt | Op::Eq => 3,
+            Op::Jnz | Op::Jz                    => 2,
+            Op::In | Op::Out | Op::Arb          => 1,
+            Op::Halt                            => 0,
+        }
+    }
+
+    // Index of the parameter the instruction writes to, if any.
+    pub fn write_param(self) -> Option<usize> {
+        match self {
+            Op::Add | Op::Mul | Op::Lt | Op::Eq => Some(2),
+            Op::In                              => Some(0),
+            _                                   => None,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Op::Add  => "add",
+            Op::Mul  => "mul",
+            Op::In   => "in",
+            Op::Out  => "out",
+            Op::Jnz  => "jnz",
+            Op::Jz   => "jz",
+            Op::Lt   => "lt",
+            Op::Eq   => "eq",
+            Op::Arb  => "arb",
+            Op::Halt => "halt",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Param {
+    pub mode:  Mode,
+    pub value: i64,
+}
+
+impl Param {
+    pub fn imm(self) -> Option<i64> {
+        if self.mode == Mode::Immediate {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Mode::Position                   => write!(f, "[{}]", self.value),
+            Mode::Immediate                  => write!(f, "{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "[rb{}]", self.value),
+            Mode::Relative                   => write!(f, "[rb+{}]", self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instr {
+    pub addr:   usize,
+    pub op:     Op,
+    pub params: Vec<Param>,
+}
+
+impl Instr {
+    pub fn next(&self) -> usize {
+        self.addr + 1 + self.params.len()
+    }
+
+    pub fn is_jump(&self) -> bool {
+        self.op == Op::Jnz || self.op == Op::Jz
+    }
+
+    // For jumps: Some(true) if always taken, Some(false) if never taken and
+    // None if it depends on the run-time value of the condition.
+    pub fn always_taken(&self) -> Option<bool> {
+        let cond = self.params[0].imm()?;
+        Some((cond != 0) == (self.op == Op::Jnz))
+    }
+
+    // Jump target if it is known statically (immediate mode).
+    pub fn jump_target(&self) -> Option<usize> {
+        if !self.is_jump() {
+            return None;
+        }
+        self.params[1].imm().filter(|&t| t >= 0).map(|t| t as usize)
+    }
+
+    // Constant written by `add`/`mul` with two immediate operands.
+    pub fn const_write(&self) -> Option<(Param, i64)> {
+        let (a, b) = (self.params.first()?.imm()?, self.params.get(1)?.imm()?);
+        match self.op {
+            Op::Add => Some((self.params[2], a.wrapping_add(b))),
+            Op::Mul => Some((self.params[2], a.wrapping_mul(b))),
+            _       => None,
+        }
+    }
+}
+
+impl Instr {
+    // Same as the `Display` output, but with addresses replaced by symbol
+    // names where possible.
+    pub fn symbolized(&self, symbols: &SymbolMap) -> String {
+        let mut out = String::from(self.op.mnemonic());
+        for (i, p) in self.params.iter().enumerate() {
+            out += if i == 0 { " " } else { ", " };
+            let addr = p.value as usize;
+            let jump = self.is_jump() && i == 1;
+            let name = match p.mode {
+                _ if p.value < 0        => None,
+                Mode::Position          => symbols.name(addr).map(|n| format!("[{}]", n)),
+                Mode::Immediate if jump => symbols.name(addr),
+                _                       => None,
+            };
+            out += &name.unwrap_or_else(|| p.to_string());
+        }
+        out
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.op.mnemonic())?;
+        for (i, p) in self.params.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, p)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn decode(mem: &[i64], addr: usize) -> Option<Instr> {
+    let instr = *mem.get(addr)?;
+    if instr < 0 {
+        return None;
+    }
+    let op         = Op::from_code(instr % 100)?;
+    let mut modes  = instr / 100;
+    let mut params = Vec::with_capacity(op.arity());
+    for i in 0..op.arity() {
+        let mode = match modes % 10 {
+            0 => Mode::Position,
+            1 => Mode::Immediate,
+            2 => Mode::Relative,
+            _ => return None,
+        };
+        params.push(Param { mode, value: *mem.get(addr + 1 + i)? });
+        modes /= 10;
+    }
+    // Surplus mode digits never occur in well-formed instructions, and
+    // write parameters cannot be immediate.
+    if modes != 0 || op.write_param().is_some_and(|w| params[w].mode == Mode::Immediate) {
+        return None;
+    }
+    Some(Instr { addr, op, params })
+}
+
+// Decodes all instructions reachable from address 0 by following statically
+// known control flow. Return addresses stored by the usual calling
+// convention (a constant written to [rb+0] before jumping) are followed too.
+pub fn reachable(mem: &[i64]) -> BTreeMap<usize, Instr> {
+    reachable_from(mem, &[0])
+}
+
+// Same as `reachable`, starting from several addresses.
+pub fn reachable_from(mem: &[i64], roots: &[usize]) -> BTreeMap<usize, Instr> {
+    let mut instrs = BTreeMap::new();
+    let mut todo   = roots.to_vec();
+    while let Some(addr) = todo.pop() {
+        if instrs.contains_key(&addr) {
+            continue;
+        }
+        let instr = match decode(mem, addr) {
+            Some(instr) => instr,
+            None        => continue,
+        };
+        if let Some((dst, val)) = instr.const_write() {
+            if dst == (Param { mode: Mode::Relative, value: 0 }) && val >= 0 {
+                todo.push(val as usize);
+            }
+        }
+        match instr.op {
+            Op::Halt => (),
+            Op::Jnz | Op::Jz => {
+                let taken = instr.always_taken();
+                if taken != Some(false) {
+                    if let Some(target) = instr.jump_target() {
+                        todo.push(target);
+                    }
+                }
+                if taken != Some(true) {
+                    todo.push(instr.next());
+                }
+            }
+            _ => todo.push(instr.next()),
+        }
+        instrs.insert(addr, instr);
+    }
+    instrs
+}
+
+// Human readable listing of the program. Everything that is not reachable
+// code is printed as data.
+pub fn listing(mem: &[i64]) -> String {
+    listing_with(mem, &SymbolMap::default())
+}
+
+// Listing with labels, symbol names and comments from a symbol map.
+pub fn listing_with(mem: &[i64], symbols: &SymbolMap) -> String {
+    let code     = reachable(mem);
+    let mut out  = String::new();
+    let mut addr = 0;
+    while addr < mem.len() {
+        let symbol = symbols.get(addr);
+        if let Some(sym) = symbol {
+            out += &format!("{}:\n", sym.name);
+        }
+        let (line, end) = if let Some(instr) = code.get(&addr) {
+            (instr.symbolized(symbols), instr.next())
+        } else if let Some(text) = symbol.and_then(|s| text_at(mem, &code, addr, s.kind)) {
+            (format!("data {:?}", text), addr + text.chars().count())
+        } else {
+            let end = (addr + 1..mem.len())
+                .take(7)
+                .find(|a| code.contains_key(a) || symbols.get(*a).is_some())
+                .unwrap_or_else(|| mem.len().min(addr + 8));
+            let words: Vec<_> = mem[addr..end].iter().map(|v| v.to_string()).collect();
+            (format!("data {}", words.join(", ")), end)
+        };
+        match symbol.and_then(|s| s.comment.as_ref()) {
+            Some(comment) => out += &format!("{:>6}: {}  ; {}\n", addr, line, comment),
+            None          => out += &format!("{:>6}: {}\n", addr, line),
+        }
+        addr = end;
+    }
+    out
+}
+
+// Contents of a string symbol if all of its cells are ASCII data.
+fn text_at(
+    mem:  &[i64],
+    code: &BTreeMap<usize, Instr>,
+    addr: usize,
+    kind: SymbolType,
+) -> Option<String> {
+    let cells = match kind {
+        SymbolType::Text(n) => mem.get(addr..addr + n)?,
+        _                   => return None,
+    };
+    let overlaps = code.range(addr..addr + cells.len()).next().is_some();
+    if overlaps || !cells.iter().all(|c| (0..128).contains(c)) {
+        return None;
+    }
+    Some(cells.iter().map(|&c| c as u8 as char).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_instructions() {
+        let mem = vec![1002, 4, 3, 4, 33, 21101, 1, -2, 0, 3];
+        assert_eq!(decode(&mem, 0).unwrap().to_string(), "mul [4], 3, [4]");
+        assert_eq!(decode(&mem, 5).unwrap().to_string(), "add 1, -2, [rb+0]");
+        assert_eq!(decode(&mem, 4), None);
+        assert_eq!(decode(&mem, 9), None);
+        assert_eq!(decode(&[11101, 1, 1, 0], 0), None);
+    }
+
+    #[test]
+    fn program_listing() {
+        // Calls the function at 15 which outputs its argument and returns.
+        let mem = vec![
+            109, 100, 21101, 0, 42, 1, 21101, 0, 14, 0, 1105, 1, 15, 99, 99, 109, 2, 204, -1, 109,
+            -2, 2105, 1, 0,
+        ];
+        let expected = "     0: arb 100\n\
+                        \x20    2: add 0, 42, [rb+1]\n\
+                        \x20    6: add 0, 14, [rb+0]\n\
+                        \x20   10: jnz 1, 15\n\
+                        \x20   13: data 99\n\
+                        \x20   14: halt\n\
+                        \x20   15: arb 2\n\
+                        \x20   17: out [rb-1]\n\
+                        \x20   19: arb -2\n\
+                        \x20   21: jnz 1, [rb+0]\n";
+        assert_eq!(listing(&mem), expected);
+    }
+
+    #[test]
+    fn symbolized_listing() {
+        // Outputs the string at 9 and the counter at 8.
+        let mem  = vec![104, 72, 4, 8, 1105, 1, 7, 99, 3, 72, 105];
+        let syms = SymbolMap::parse("7 done code\n8 count ; number of runs\n9 greeting string[2]")
+            .unwrap();
+        let expected = "     0: out 72\n\
+                        \x20    2: out [count]\n\
+                        \x20    4: jnz 1, done\n\
+                        done:\n\
+                        \x20    7: halt\n\
+                        count:\n\
+                        \x20    8: data 3  ; number of runs\n\
+                        greeting:\n\
+                        \x20    9: data \"Hi\"\n";
+        assert_eq!(listing_with(&mem, &syms), expected);
+    }
+}
@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::isa::Isa;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntcodeError {
+    InvalidOpCode { ip: usize, op_code: i64 },
+    InvalidMode { ip: usize, mode: i64 },
+    UnsupportedOpCode { ip: usize, op_code: i64, isa: Isa },
+    UnsupportedMode { ip: usize, mode: i64, isa: Isa },
+    MissingInput { ip: usize },
+    IncompleteOutput { ip: usize, count: usize },
+    InvalidAddress { ip: usize, addr: i128 },
+    Overflow { ip: usize },
+    ReservedOpCode { op_code: i64 },
+    InvalidExtension { op_code: i64 },
+    Extension { ip: usize, op_code: i64, msg: String },
+    CodeWrite { ip: usize, addr: usize },
+    ProtectedWrite { ip: usize, instr: String, addr: usize },
+    JumpIntoData { ip: usize, instr: String, target: usize },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::InvalidOpCode { ip, op_code } => {
+                write!(f, "Invalid op code {} at position {}!", op_code, ip)
+            }
+            IntcodeError::InvalidMode { ip, mode } => {
+                write!(f, "Invalid parameter mode {} at position {}!", mode, ip)
+            }
+            IntcodeError::UnsupportedOpCode { ip, op_code, isa } => write!(
+                f,
+                "Op code {} at position {} is not supported by the {} instruction set!",
+                op_code, ip, isa
+            ),
+            IntcodeError::UnsupportedMode { ip, mode, isa } => write!(
+                f,
+                "Parameter mode {} at position {} is not supported by the {} instruction set!",
+                mode, ip, isa
+            ),
+            IntcodeError::MissingInput { ip } => {
+                write!(f, "Missing input at position {}!", ip)
+            }
+            IntcodeError::IncompleteOutput { ip, count } => {
+                write!(f, "Program halted at position {} with {} values of an incomplete output!", ip, count)
+            }
+            IntcodeError::InvalidAddress { ip, addr } => {
+                write!(f, "Invalid address {} at position {}!", addr, ip)
+            }
+            IntcodeError::Overflow { ip } => {
+                write!(f, "Arithmetic overflow at position {}!", ip)
+            }
+            IntcodeError::ReservedOpCode { op_code } => {
+                write!(f, "Op code {} cannot be used for an extension!", op_code)
+            }
+            IntcodeError::InvalidExtension { op_code } => {
+                write!(f, "Extension for op code {} writes to an undeclared parameter!", op_code)
+            }
+            IntcodeError::Extension { ip, op_code, msg } => {
+                write!(f, "Extension op code {} failed at position {}: {}", op_code, ip, msg)
+            }
+            IntcodeError::CodeWrite { ip, addr } => {
+                write!(f, "Instruction at position {} writes to code at address {}!", ip, addr)
+            }
+            IntcodeError::ProtectedWrite { ip, instr, addr } => write!(
+                f,
+                "Instruction '{}' at position {} writes to protected code at address {}!",
+                instr, ip, addr
+            ),
+            IntcodeError::JumpIntoData { ip, instr, target } => write!(
+                f,
+                "Instruction '{}' at position {} jumps to {}, where no instruction starts!",
+                instr, ip, target
+            ),
+        }
+    }
+}
+
+impl Error for IntcodeError {}
@@ -0,0 +1,37 @@
+// Custom op codes outside of the standard instruction set.
+
+use std::collections::VecDeque;
+
+use crate::ProgramStatus;
+use crate::Word;
+
+// Receives the decoded parameters of the instruction. Read parameters hold
+// the value they refer to, write parameters hold the current value of their
+// target and are stored back if the handler returns `Success`. If the handler
+// returns `WaitingForInput` or `Finished`, nothing is written and the
+// instruction pointer stays on the custom instruction.
+//
+// Handlers are plain functions, which keeps extensions `Copy` and machines
+// `Send`, so they cannot capture state. Whatever state a handler needs has to
+// live in the machine's memory (passed as a parameter) or go through the
+// input and output queues.
+pub type OpHandler<W> = fn(
+    args:   &mut [W],
+    input:  &mut VecDeque<W>,
+    output: &mut VecDeque<W>,
+) -> Result<ProgramStatus, String>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Extension<W: Word> {
+    // Number of parameters following the op code.
+    pub arity:   usize,
+    // Indices (starting at 0) of the parameters that are written.
+    pub writes:  &'static [usize],
+    pub handler: OpHandler<W>,
+}
+
+impl<W: Word> Extension<W> {
+    pub fn new(arity: usize, writes: &'static [usize], handler: OpHandler<W>) -> Extension<W> {
+        Extension { arity, writes, handler }
+    }
+}
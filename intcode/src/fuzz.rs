@@ -0,0 +1,304 @@
+// Differential fuzzing of Intcode interpreters.
+//
+// Random programs restricted to the day 2 opcode subset (1, 2 and 99) are
+// executed by two engines and their final memories are compared. Panics and
+// hangs are caught per engine, so a faulty engine cannot take down the run.
+// Optionally, the programs also contain conditional jumps (op codes 5 and 6
+// with an immediate target), which can loop forever.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::IntcodeProg;
+use crate::ProgramStatus;
+
+// An engine runs a program to completion and returns its final memory.
+pub type Engine = fn(&[i64]) -> Vec<i64>;
+
+// Small xorshift64* generator, good enough for reproducible fuzzing.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // A zero state would only ever produce zeros.
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GenConfig {
+    // Maximum number of add/multiply instructions before the final 99.
+    pub max_instrs:     usize,
+    // Number of data cells placed behind the code.
+    pub data_len:       usize,
+    // Data cells are initialized with values in 0..=max_value.
+    pub max_value:      i64,
+    // If set, operands may point anywhere (including into the code),
+    // otherwise all reads and writes stay within the data region.
+    pub self_modifying: bool,
+    // If set, some instructions are jumps to the start of an instruction,
+    // taken depending on a data cell.
+    pub jumps:          bool,
+}
+
+impl Default for GenConfig {
+    fn default() -> GenConfig {
+        // With at most 4 instructions on values up to 3, results stay below
+        // 3^16 and thus fit into an i32 even for repeated squaring.
+        GenConfig {
+            max_instrs:     4,
+            data_len:       8,
+            max_value:      3,
+            self_modifying: false,
+            jumps:          false,
+        }
+    }
+}
+
+pub fn gen_program(rng: &mut Rng, cfg: &GenConfig) -> Vec<i64> {
+    let num_instrs = rng.below(cfg.max_instrs + 1);
+    // Jumps have two parameters, all other instructions three.
+    let jumps: Vec<_> = (0..num_instrs).map(|_| cfg.jumps && rng.below(3) == 0).collect();
+    let mut starts    = Vec::with_capacity(num_instrs + 1);
+    let mut code_len  = 0;
+    for &jump in jumps.iter() {
+        starts.push(code_len);
+        code_len += if jump { 3 } else { 4 };
+    }
+    starts.push(code_len);
+    // The halt instruction is padded to a full 4-word instruction since
+    // day 2 style interpreters fetch all operands before dispatching.
+    code_len      += 4;
+    let total_len  = code_len + cfg.data_len.max(1);
+    let addr       = |rng: &mut Rng| {
+        if cfg.self_modifying {
+            rng.below(total_len) as i64
+        } else {
+            (code_len + rng.below(total_len - code_len)) as i64
+        }
+    };
+
+    let mut prog = Vec::with_capacity(total_len);
+    for &jump in jumps.iter() {
+        if jump {
+            prog.push(if rng.below(2) == 0 { 1005 } else { 1006 });
+            prog.push(addr(rng));
+            prog.push(starts[rng.below(starts.len())] as i64);
+        } else {
+            prog.push(if rng.below(2) == 0 { 1 } else { 2 });
+            prog.push(addr(rng));
+            prog.push(addr(rng));
+            prog.push(addr(rng));
+        }
+    }
+    prog.extend_from_slice(&[99, 0, 0, 0]);
+    while prog.len() < total_len {
+        prog.push(rng.below(cfg.max_value as usize + 1) as i64);
+    }
+    prog
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Halted(Vec<i64>),
+    Panicked(String),
+    Hung,
+}
+
+impl Outcome {
+    // Two engines agree if they halt with the same memory (ignoring memory
+    // that one of them merely zero-extended), if both reject the program or
+    // if neither of them halts.
+    fn agrees_with(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Halted(m1), Outcome::Halted(m2))   => trim_zeros(m1) == trim_zeros(m2),
+            (Outcome::Panicked(_), Outcome::Panicked(_)) => true,
+            (Outcome::Hung, Outcome::Hung)               => true,
+            _                                            => false,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Halted(mem)   => write!(f, "halted with memory {:?}", mem),
+            Outcome::Panicked(msg) => write!(f, "panicked: {}", msg),
+            Outcome::Hung          => write!(f, "did not halt in time"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Mismatch {
+    pub case:      usize,
+    pub prog:      Vec<i64>,
+    pub reference: Outcome,
+    pub candidate: Outcome,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "case {}: {:?}", self.case, self.prog)?;
+        writeln!(f, "  reference {}", self.reference)?;
+        write!(f, "  candidate {}", self.candidate)
+    }
+}
+
+fn trim_zeros(mem: &[i64]) -> &[i64] {
+    let len = mem.iter().rposition(|&v| v != 0).map_or(0, |i| i + 1);
+    &mem[..len]
+}
+
+// Runs the engine on a separate thread so that panics and endless loops
+// are reported instead of aborting (or blocking) the fuzzer. A hanging
+// engine keeps its thread busy until the process exits.
+pub fn run_engine(engine: Engine, prog: &[i64], timeout: Duration) -> Outcome {
+    let (tx, rx) = mpsc::channel();
+    let prog     = prog.to_vec();
+    thread::spawn(move || {
+        let result = panic::catch_unwind(|| engine(&prog));
+        let _      = tx.send(result.map_err(panic_message));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(mem))  => Outcome::Halted(mem),
+        Ok(Err(msg)) => Outcome::Panicked(msg),
+        Err(_)       => Outcome::Hung,
+    }
+}
+
+pub(crate) fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        String::from("<unknown panic>")
+    }
+}
+
+pub fn differential(
+    reference: Engine,
+    candidate: Engine,
+    cfg:       &GenConfig,
+    seed:      u64,
+    cases:     usize,
+) -> Vec<Mismatch> {
+    let timeout = Duration::from_secs(1);
+    let mut rng = Rng::new(seed);
+    (0..cases)
+        .filter_map(|case| {
+            let prog = gen_program(&mut rng, cfg);
+            let ref_out = run_engine(reference, &prog, timeout);
+            let can_out = run_engine(candidate, &prog, timeout);
+            if ref_out.agrees_with(&can_out) {
+                None
+            } else {
+                Some(Mismatch {
+                    case,
+                    prog,
+                    reference: ref_out,
+                    candidate: can_out,
+                })
+            }
+        })
+        .collect()
+}
+
+// Engine for the shared IntcodeProg interpreter.
+pub fn intcode_engine(prog: &[i64]) -> Vec<i64> {
+    let mut prog   = IntcodeProg::new(prog);
+    let mut input  = VecDeque::new();
+    let mut output = VecDeque::new();
+    loop {
+        match prog.exec_instr(&mut input, &mut output) {
+            ProgramStatus::Success         => (),
+            ProgramStatus::Finished        => break prog.mem.to_vec(),
+            ProgramStatus::WaitingForInput => panic!("Missing input!"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn broken_engine(prog: &[i64]) -> Vec<i64> {
+        // Treats every multiplication as an addition.
+        let prog: Vec<_> = prog.iter().map(|&v| if v == 2 { 1 } else { v }).collect();
+        intcode_engine(&prog)
+    }
+
+    fn hanging_engine(_prog: &[i64]) -> Vec<i64> {
+        thread::sleep(Duration::from_secs(5));
+        Vec::new()
+    }
+
+    #[test]
+    fn generated_programs() {
+        let cfg     = GenConfig::default();
+        let mut rng = Rng::new(1);
+        for _ in 0..100 {
+            let prog = gen_program(&mut rng, &cfg);
+            assert_eq!(prog.len() % 4, 0);
+            let halt = prog.iter().step_by(4).position(|&op| op == 99).unwrap();
+            for instr in prog[..halt * 4].chunks(4) {
+                assert!(instr[0] == 1 || instr[0] == 2);
+                assert!(instr[1..].iter().all(|&p| p as usize >= (halt + 1) * 4));
+            }
+        }
+    }
+
+    #[test]
+    fn self_consistency() {
+        let cfg = GenConfig::default();
+        assert!(differential(intcode_engine, intcode_engine, &cfg, 7, 200).is_empty());
+    }
+
+    #[test]
+    fn detects_mismatch() {
+        let cfg = GenConfig::default();
+        assert!(!differential(intcode_engine, broken_engine, &cfg, 7, 200).is_empty());
+    }
+
+    #[test]
+    fn jumps_and_self_modification() {
+        // The first 13 programs for seed 1 include a hanging and a
+        // panicking one.
+        let cfg     = GenConfig { self_modifying: true, jumps: true, ..GenConfig::default() };
+        let mut rng = Rng::new(1);
+        let outcomes: Vec<_> = (0..13)
+            .map(|_| run_engine(intcode_engine, &gen_program(&mut rng, &cfg), Duration::from_millis(100)))
+            .collect();
+        assert!(outcomes.contains(&Outcome::Hung));
+        assert!(outcomes.iter().any(|out| matches!(out, Outcome::Panicked(_))));
+        assert!(differential(intcode_engine, intcode_engine, &cfg, 1, 13).is_empty());
+    }
+
+    #[test]
+    fn detects_panic_and_hang() {
+        let prog = vec![42, 0, 0, 0];
+        match run_engine(intcode_engine, &prog, Duration::from_secs(1)) {
+            Outcome::Panicked(msg) => assert_eq!(msg, "Invalid op code 42 at position 0!"),
+            out                    => panic!("unexpected outcome: {}", out),
+        }
+        let out = run_engine(hanging_engine, &prog, Duration::from_millis(50));
+        assert_eq!(out, Outcome::Hung);
+    }
+}
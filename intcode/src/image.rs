@@ -0,0 +1,133 @@
+// Binary program images.
+//
+// Programs are normally stored as comma-separated text. Images hold the
+// same words in a compact binary form:
+//
+//     magic     `ICIMAGE`
+//     version   one byte, currently 1
+//     length    varint number of words
+//     words     one zigzag varint per word
+//     checksum  FNV-1a hash of the length and words, 4 bytes little endian
+//
+// Small values take one byte, so images are usually less than half the
+// size of the text. `load_program` accepts both formats.
+
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use crate::trace::read_signed;
+use crate::trace::read_u64;
+use crate::trace::write_signed;
+use crate::trace::write_varint;
+
+pub const MAGIC: &[u8; 7] = b"ICIMAGE";
+pub const VERSION: u8     = 1;
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &b| (hash ^ b as u32).wrapping_mul(0x0100_0193))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub fn is_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(prog: &[i64]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    // Writing to a Vec cannot fail.
+    write_varint(&mut payload, prog.len() as u64).unwrap();
+    for &word in prog {
+        write_signed(&mut payload, word).unwrap();
+    }
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend_from_slice(&payload);
+    bytes.extend_from_slice(&checksum(&payload).to_le_bytes());
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<Vec<i64>> {
+    if !is_image(bytes) {
+        return Err(invalid(String::from("not an Intcode image")));
+    }
+    match bytes.get(MAGIC.len()) {
+        Some(&VERSION) => (),
+        Some(version)  => return Err(invalid(format!("unsupported image version {}", version))),
+        None           => return Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+    let payload = &bytes[MAGIC.len() + 1..];
+    if payload.len() < 4 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (payload, sum) = payload.split_at(payload.len() - 4);
+    if checksum(payload).to_le_bytes() != sum {
+        return Err(invalid(String::from("image checksum mismatch")));
+    }
+    let mut input = payload;
+    let len       = read_u64(&mut input)? as usize;
+    // Every word takes at least one byte.
+    if len > input.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let mut prog = Vec::with_capacity(len);
+    for _ in 0..len {
+        prog.push(read_signed(&mut input)?);
+    }
+    if !input.is_empty() {
+        return Err(invalid(String::from("trailing data after the image")));
+    }
+    Ok(prog)
+}
+
+pub fn write<Wr: Write>(out: &mut Wr, prog: &[i64]) -> io::Result<()> {
+    out.write_all(&encode(prog))
+}
+
+pub fn read<R: Read>(input: &mut R) -> io::Result<Vec<i64>> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    decode(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn round_trip() {
+        let prog  = vec![109, -1, 21101, 0, i64::MAX, i64::MIN, 99];
+        let bytes = encode(&prog);
+        assert_eq!(bytes.len(), 8 + 1 + 2 + 1 + 3 + 1 + 10 + 10 + 2 + 4);
+        assert_eq!(decode(&bytes).unwrap(), prog);
+        assert_eq!(read(&mut &bytes[..]).unwrap(), prog);
+        assert_eq!(decode(&encode(&[])).unwrap(), vec![]);
+
+        let mut corrupt = bytes.clone();
+        corrupt[12] ^= 1;
+        assert_eq!(decode(&corrupt).unwrap_err().to_string(), "image checksum mismatch");
+        corrupt = bytes.clone();
+        corrupt[7] = 2;
+        assert_eq!(decode(&corrupt).unwrap_err().to_string(), "unsupported image version 2");
+        assert!(decode(&bytes[..10]).is_err());
+        assert!(decode(b"1,2,3").is_err());
+    }
+
+    #[test]
+    fn load_detects_format() {
+        let dir  = env::temp_dir();
+        let text = dir.join(format!("intcode-image-{}.txt", std::process::id()));
+        let bin  = text.with_extension("bin");
+        fs::write(&text, "1,0,0,0,99\n").unwrap();
+        fs::write(&bin, encode(&[1, 0, 0, 0, 99])).unwrap();
+        assert_eq!(crate::load_program(text.to_str().unwrap()).unwrap(), vec![1, 0, 0, 0, 99]);
+        assert_eq!(crate::load_program(bin.to_str().unwrap()).unwrap(), vec![1, 0, 0, 0, 99]);
+        fs::remove_file(text).unwrap();
+        fs::remove_file(bin).unwrap();
+    }
+}
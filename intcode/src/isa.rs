@@ -0,0 +1,56 @@
+// Instruction set revisions as they were introduced by the puzzles.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isa {
+    // Day 2: add, multiply and halt, position mode only.
+    Day2,
+    // Day 5: adds input/output, jumps, comparisons and immediate mode.
+    Day5,
+    // Day 9: adds relative mode and opcode 9 (the complete instruction set).
+    #[default]
+    Day9,
+}
+
+impl Isa {
+    pub fn supports_op(self, op_code: i64) -> bool {
+        match op_code {
+            1 | 2 | 99 => true,
+            3..=8      => self != Isa::Day2,
+            9          => self == Isa::Day9,
+            _          => false,
+        }
+    }
+
+    pub fn supports_mode(self, mode: i64) -> bool {
+        match self {
+            Isa::Day2 => mode == 0,
+            Isa::Day5 => mode == 0 || mode == 1,
+            Isa::Day9 => (0..=2).contains(&mode),
+        }
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Isa::Day2 => write!(f, "day 2"),
+            Isa::Day5 => write!(f, "day 5"),
+            Isa::Day9 => write!(f, "day 9"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_op_codes() {
+        let ops = |isa: Isa| (0..=99).filter(|&op| isa.supports_op(op)).collect::<Vec<_>>();
+        assert_eq!(ops(Isa::Day2), vec![1, 2, 99]);
+        assert_eq!(ops(Isa::Day5), vec![1, 2, 3, 4, 5, 6, 7, 8, 99]);
+        assert_eq!(ops(Isa::Day9), vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 99]);
+    }
+}
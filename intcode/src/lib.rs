@@ -1,6 +1,74 @@
+use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::num::ParseIntError;
+use std::ops::Range;
 
-#[derive(PartialEq)]
+pub mod async_io;
+pub mod callstack;
+pub mod cfg;
+pub mod compiler;
+pub mod debug;
+pub mod decompile;
+pub mod disasm;
+pub mod error;
+pub mod extension;
+pub mod fuzz;
+pub mod image;
+pub mod isa;
+pub mod memory;
+pub mod object;
+pub mod pool;
+pub mod protect;
+pub mod scanner;
+pub mod smc;
+pub mod stream;
+pub mod strings;
+pub mod symbols;
+pub mod taint;
+pub mod testcase;
+pub mod trace;
+pub mod word;
+
+pub use error::IntcodeError;
+pub use extension::Extension;
+pub use isa::Isa;
+pub use memory::Memory;
+pub use smc::CodeWrites;
+pub use word::Overflow;
+pub use word::Word;
+
+use disasm::Op;
+use protect::CodeRegion;
+use smc::CodeWrite;
+use smc::SmcTracker;
+use taint::TaintTracker;
+use taint::Update;
+
+pub fn parse_program(text: &str) -> Result<Vec<i64>, ParseIntError> {
+    text.trim().split(',').map(|s| s.trim().parse()).collect()
+}
+
+// Loads a program from a text file or a binary image (see the image module).
+pub fn load_program(path: &str) -> Result<Vec<i64>, Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    if image::is_image(&bytes) {
+        return Ok(image::decode(&bytes)?);
+    }
+    Ok(parse_program(&String::from_utf8(bytes)?)?)
+}
+
+// Word as seen by the disassembler. Words that do not fit into an i64 become
+// -1, which is not a valid instruction.
+pub(crate) fn decode_word<W: Word>(word: W) -> i64 {
+    i64::from_i128(word.to_i128()).unwrap_or(-1)
+}
+
+#[derive(Debug, PartialEq)]
 pub enum ProgramStatus {
     Success,
     WaitingForInput,
@@ -8,147 +76,527 @@ pub enum ProgramStatus {
 }
 
 #[derive(Debug, Clone)]
-pub struct IntcodeProg {
-    mem:      Vec<i64>,
-    ip:       usize,
-    rel_base: i64,
+pub struct IntcodeProg<W: Word = i64> {
+    mem:         Memory<W>,
+    // Memory as loaded, restored by `reset`.
+    image:       Memory<W>,
+    ip:          usize,
+    rel_base:    i64,
+    isa:         Isa,
+    overflow:    Overflow,
+    extensions:  HashMap<i64, Extension<W>>,
+    // Memory cell written by the last executed instruction.
+    last_write:  Option<(usize, W)>,
+    // Self-modifying code detection, see the smc module.
+    code_writes: CodeWrites,
+    smc:         SmcTracker<W>,
+    // Execute-only code region, see the protect module.
+    protected:   Option<CodeRegion>,
+    // Taint tracking, see the taint module.
+    taint:       Option<TaintTracker>,
+}
+
+// Two machines are equal if they behave identically from here on, given the
+// same configuration: memory (ignoring trailing zeros), ip and rel_base match.
+// The ISA, overflow policy and extensions are not compared.
+impl<W: Word> PartialEq for IntcodeProg<W> {
+    fn eq(&self, other: &IntcodeProg<W>) -> bool {
+        self.ip == other.ip && self.rel_base == other.rel_base && self.mem == other.mem
+    }
+}
+
+impl<W: Word> Eq for IntcodeProg<W> {}
+
+impl<W: Word> Hash for IntcodeProg<W> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fingerprint().hash(state);
+    }
 }
 
 impl IntcodeProg {
     pub fn new(prog: &[i64]) -> IntcodeProg {
+        IntcodeProg::with_isa(prog, Isa::default())
+    }
+
+    pub fn with_isa(prog: &[i64], isa: Isa) -> IntcodeProg {
+        IntcodeProg::from_words(prog, isa)
+    }
+
+    pub fn exec_prog(prog: &[i64], input: Vec<i64>) -> VecDeque<i64> {
+        IntcodeProg::try_exec_prog(prog, Isa::default(), input)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_exec_prog(
+        prog:  &[i64],
+        isa:   Isa,
+        input: Vec<i64>,
+    ) -> Result<VecDeque<i64>, IntcodeError> {
+        IntcodeProg::with_isa(prog, isa).run(input)
+    }
+}
+
+impl<W: Word> IntcodeProg<W> {
+    // Generic constructor for word types other than i64, e.g.
+    // `IntcodeProg::<i128>::from_words(&prog, Isa::Day9)`.
+    pub fn from_words(prog: &[W], isa: Isa) -> IntcodeProg<W> {
+        let mem = Memory::from_slice(prog);
         IntcodeProg {
-            mem:      prog.to_vec(),
-            ip:       0,
-            rel_base: 0,
+            image:       mem.clone(),
+            mem,
+            ip:          0,
+            rel_base:    0,
+            isa,
+            overflow:    Overflow::default(),
+            extensions:  HashMap::new(),
+            last_write:  None,
+            code_writes: CodeWrites::default(),
+            smc:         SmcTracker::default(),
+            protected:   None,
+            taint:       None,
         }
     }
 
-    pub fn exec_prog(prog: &[i64], input: Vec<i64>) -> VecDeque<i64> {
-        let mut prog   = IntcodeProg::new(prog);
+    pub fn mem(&self) -> &Memory<W> {
+        &self.mem
+    }
+
+    // Restores the machine to the program it was created with. Only memory
+    // pages that were written to are copied. The ISA, overflow policy and
+    // extensions are kept.
+    pub fn reset(&mut self) {
+        self.mem.restore(&self.image);
+        self.ip         = 0;
+        self.rel_base   = 0;
+        self.last_write = None;
+        self.smc.clear();
+        if let Some(taint) = self.taint.as_mut() {
+            taint.clear();
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn rel_base(&self) -> i64 {
+        self.rel_base
+    }
+
+    // Reads a memory cell. As for parameters, cells beyond the initialized
+    // memory are zero and negative addresses are invalid.
+    pub fn peek(&self, addr: i64) -> Result<W, IntcodeError> {
+        let addr = self.to_addr(addr as i128)?;
+        Ok(self.mem.get(addr).unwrap_or(W::ZERO))
+    }
+
+    // Writes a memory cell, growing the memory if necessary.
+    pub fn poke(&mut self, addr: i64, val: W) -> Result<(), IntcodeError> {
+        let addr = self.to_addr(addr as i128)?;
+        self.mem.set(addr, val);
+        Ok(())
+    }
+
+    // Contents of a memory range, zero-filled beyond the initialized memory.
+    pub fn dump_range(&self, range: Range<usize>) -> Vec<W> {
+        range.map(|addr| self.mem.get(addr).unwrap_or(W::ZERO)).collect()
+    }
+
+    // Copy of the machine that shares all memory pages with the original
+    // until either of them writes to a page. Plain `clone()` does the same.
+    pub fn fork(&self) -> IntcodeProg<W> {
+        self.clone()
+    }
+
+    // Cheap hash of the machine state, kept up to date incrementally. Equal
+    // machines have equal fingerprints.
+    pub fn fingerprint(&self) -> u64 {
+        let regs = (self.ip as u64) ^ (self.rel_base as u64).rotate_left(32);
+        self.mem.fingerprint() ^ regs.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    }
+
+    // Position and value of the memory cell written by the last executed
+    // instruction (the last one for extensions writing several cells).
+    pub fn last_write(&self) -> Option<(usize, W)> {
+        self.last_write
+    }
+
+    pub fn set_overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    // Enables tracking of writes into executed code. Only cells executed
+    // after this call count as code.
+    pub fn set_code_writes(&mut self, code_writes: CodeWrites) {
+        self.code_writes = code_writes;
+    }
+
+    // Writes into code recorded so far.
+    pub fn code_writes(&self) -> &[CodeWrite<W>] {
+        self.smc.writes()
+    }
+
+    // Cells that were both executed and written so far, in either order.
+    pub fn self_modified(&self) -> Vec<usize> {
+        self.smc.modified()
+    }
+
+    // Makes the code reachable from address 0 in the current memory
+    // execute-only, see the protect module. Writes to its op codes and jumps
+    // into its instructions fail. The protection is kept by `reset`.
+    pub fn protect_code(&mut self) {
+        let mem: Vec<_> = self.mem.iter().map(decode_word).collect();
+        self.protected  = Some(CodeRegion::from_mem(&mem));
+    }
+
+    pub fn unprotect_code(&mut self) {
+        self.protected = None;
+    }
+
+    pub fn code_region(&self) -> Option<&CodeRegion> {
+        self.protected.as_ref()
+    }
+
+    // Starts tracking which inputs influence which outputs. Only inputs
+    // consumed after this call are labeled.
+    pub fn enable_taint(&mut self) {
+        let mem: Vec<_> = self.mem.iter().map(decode_word).collect();
+        self.taint      = Some(TaintTracker::for_program(&mem));
+    }
+
+    pub fn disable_taint(&mut self) {
+        self.taint = None;
+    }
+
+    pub fn taint(&self) -> Option<&TaintTracker> {
+        self.taint.as_ref()
+    }
+
+    // Registers a handler for an op code that is not part of the complete
+    // instruction set. Custom op codes are accepted regardless of the ISA,
+    // and so are all parameter modes of their parameters.
+    pub fn register_op(&mut self, op_code: i64, ext: Extension<W>) -> Result<(), IntcodeError> {
+        if !(0..100).contains(&op_code) || Isa::Day9.supports_op(op_code) {
+            return Err(IntcodeError::ReservedOpCode { op_code });
+        }
+        if ext.writes.iter().any(|&w| w >= ext.arity) {
+            return Err(IntcodeError::InvalidExtension { op_code });
+        }
+        self.extensions.insert(op_code, ext);
+        Ok(())
+    }
+
+    // Runs the program to completion with the given input.
+    pub fn run(&mut self, input: Vec<W>) -> Result<VecDeque<W>, IntcodeError> {
         let mut output = VecDeque::new();
         let mut inputs = VecDeque::new();
         input.iter().for_each(|e| inputs.push_back(*e));
         loop {
-            match prog.exec_instr(&mut inputs, &mut output) {
+            match self.try_exec_instr(&mut inputs, &mut output)? {
                 ProgramStatus::Success         => (),
-                ProgramStatus::Finished        => break output,
-                ProgramStatus::WaitingForInput => panic!("Missing input!"),
+                ProgramStatus::Finished        => break Ok(output),
+                ProgramStatus::WaitingForInput => {
+                    break Err(IntcodeError::MissingInput { ip: self.ip })
+                }
             }
         }
     }
 
     pub fn exec_instr(
         &mut self,
-        input:  &mut VecDeque<i64>,
-        output: &mut VecDeque<i64>,
+        input:  &mut VecDeque<W>,
+        output: &mut VecDeque<W>,
     ) -> ProgramStatus {
-        let op_code =  self.mem[self.ip] %     100;
-        let mode1   = (self.mem[self.ip] %   1_000) /    100;
-        let mode2   = (self.mem[self.ip] %  10_000) /  1_000;
-        let mode3   = (self.mem[self.ip] % 100_000) / 10_000;
+        self.try_exec_instr(input, output)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_exec_instr(
+        &mut self,
+        input:  &mut VecDeque<W>,
+        output: &mut VecDeque<W>,
+    ) -> Result<ProgramStatus, IntcodeError> {
+        if self.taint.is_none() {
+            return self.exec(input, output);
+        }
+        let update            = self.taint_update();
+        let (inputs, outputs) = (input.len(), output.len());
+        let status            = self.exec(input, output)?;
+        if let (ProgramStatus::Success, Some(update)) = (&status, update) {
+            let consumed = inputs.saturating_sub(input.len());
+            let taint    = self.taint.as_mut().unwrap();
+            taint.apply(update, consumed, output.len() - outputs);
+        }
+        Ok(status)
+    }
+
+    fn exec(
+        &mut self,
+        input:  &mut VecDeque<W>,
+        output: &mut VecDeque<W>,
+    ) -> Result<ProgramStatus, IntcodeError> {
+        // Cells past the end of memory read as zero, so running off the
+        // end is an invalid op code.
+        let instr   = self.mem.get(self.ip).unwrap_or(W::ZERO).to_i128();
+        let op_code = (instr %     100) as i64;
+        let mode1   = (instr %   1_000  /    100) as i64;
+        let mode2   = (instr %  10_000  /  1_000) as i64;
+        let mode3   = (instr % 100_000  / 10_000) as i64;
+        self.last_write = None;
+        if let Some(&ext) = self.extensions.get(&op_code) {
+            self.check_patched(1 + ext.arity)?;
+            return self.exec_extension(op_code, instr, ext, input, output);
+        }
+        if !self.isa.supports_op(op_code) {
+            return Err(if Isa::Day9.supports_op(op_code) {
+                IntcodeError::UnsupportedOpCode { ip: self.ip, op_code, isa: self.isa }
+            } else {
+                IntcodeError::InvalidOpCode { ip: self.ip, op_code }
+            });
+        }
+        self.check_patched(1 + Op::from_code(op_code).unwrap().arity())?;
         match op_code {
             1 => {
                 // Add
-                let pos1        = self.get_pos(mode1, self.ip + 1);
-                let pos2        = self.get_pos(mode2, self.ip + 2);
-                let pos3        = self.get_pos(mode3, self.ip + 3);
-                self.mem[pos3]  = self.mem[pos1] + self.mem[pos2];
+                let pos1        = self.get_pos(mode1, self.ip + 1)?;
+                let pos2        = self.get_pos(mode2, self.ip + 2)?;
+                let pos3        = self.get_pos(mode3, self.ip + 3)?;
+                let val         = self.overflow
+                    .add(self.mem[pos1], self.mem[pos2])
+                    .ok_or(IntcodeError::Overflow { ip: self.ip })?;
+                self.write(pos3, val)?;
                 self.ip        += 4;
             }
             2 => {
                 // Multiply
-                let pos1        = self.get_pos(mode1, self.ip + 1);
-                let pos2        = self.get_pos(mode2, self.ip + 2);
-                let pos3        = self.get_pos(mode3, self.ip + 3);
-                self.mem[pos3]  = self.mem[pos1] * self.mem[pos2];
+                let pos1        = self.get_pos(mode1, self.ip + 1)?;
+                let pos2        = self.get_pos(mode2, self.ip + 2)?;
+                let pos3        = self.get_pos(mode3, self.ip + 3)?;
+                let val         = self.overflow
+                    .mul(self.mem[pos1], self.mem[pos2])
+                    .ok_or(IntcodeError::Overflow { ip: self.ip })?;
+                self.write(pos3, val)?;
                 self.ip        += 4;
             }
             3 => {
                 // Input
                 if let Some(val) = input.pop_front() {
-                    let pos        = self.get_pos(mode1, self.ip + 1);
-                    self.mem[pos]  = val;
-                    self.ip       += 2;
+                    let pos  = self.get_pos(mode1, self.ip + 1)?;
+                    self.write(pos, val)?;
+                    self.ip += 2;
                 } else {
-                    return ProgramStatus::WaitingForInput;
+                    return Ok(ProgramStatus::WaitingForInput);
                 }
             }
             4 => {
                 // Output
-                let pos = self.get_pos(mode1, self.ip + 1);
+                let pos = self.get_pos(mode1, self.ip + 1)?;
                 output.push_back(self.mem[pos]);
                 self.ip += 2;
             }
             5 => {
                 // Jump-If-True
-                let pos1 = self.get_pos(mode1, self.ip + 1);
-                let pos2 = self.get_pos(mode2, self.ip + 2);
-                if self.mem[pos1] != 0 {
-                    self.ip = self.mem[pos2] as usize;
+                let pos1 = self.get_pos(mode1, self.ip + 1)?;
+                let pos2 = self.get_pos(mode2, self.ip + 2)?;
+                if self.mem[pos1] != W::ZERO {
+                    self.jump(self.mem[pos2])?;
                 } else {
                     self.ip += 3;
                 }
             }
             6 => {
                 // Jump-If-False
-                let pos1 = self.get_pos(mode1, self.ip + 1);
-                let pos2 = self.get_pos(mode2, self.ip + 2);
-                if self.mem[pos1] == 0 {
-                    self.ip = self.mem[pos2] as usize;
+                let pos1 = self.get_pos(mode1, self.ip + 1)?;
+                let pos2 = self.get_pos(mode2, self.ip + 2)?;
+                if self.mem[pos1] == W::ZERO {
+                    self.jump(self.mem[pos2])?;
                 } else {
                     self.ip += 3;
                 }
             }
             7 => {
                 // Less Than
-                let pos1 = self.get_pos(mode1, self.ip + 1);
-                let pos2 = self.get_pos(mode2, self.ip + 2);
-                let pos3 = self.get_pos(mode3, self.ip + 3);
+                let pos1 = self.get_pos(mode1, self.ip + 1)?;
+                let pos2 = self.get_pos(mode2, self.ip + 2)?;
+                let pos3 = self.get_pos(mode3, self.ip + 3)?;
                 if self.mem[pos1] < self.mem[pos2] {
-                    self.mem[pos3] = 1;
+                    self.write(pos3, W::ONE)?;
                 } else {
-                    self.mem[pos3] = 0;
+                    self.write(pos3, W::ZERO)?;
                 }
                 self.ip += 4;
             }
             8 => {
                 // Equals
-                let pos1 = self.get_pos(mode1, self.ip + 1);
-                let pos2 = self.get_pos(mode2, self.ip + 2);
-                let pos3 = self.get_pos(mode3, self.ip + 3);
+                let pos1 = self.get_pos(mode1, self.ip + 1)?;
+                let pos2 = self.get_pos(mode2, self.ip + 2)?;
+                let pos3 = self.get_pos(mode3, self.ip + 3)?;
                 if self.mem[pos1] == self.mem[pos2] {
-                    self.mem[pos3] = 1;
+                    self.write(pos3, W::ONE)?;
                 } else {
-                    self.mem[pos3] = 0;
+                    self.write(pos3, W::ZERO)?;
                 }
                 self.ip += 4;
             }
             9 => {
                 // Adjust the Relative Base
-                let pos        = self.get_pos(mode1, self.ip + 1);
-                self.rel_base += self.mem[pos];
+                // Only the effective addresses have to be valid, the
+                // relative base itself may be negative.
+                let pos        = self.get_pos(mode1, self.ip + 1)?;
+                let rel_base   = self.rel_base as i128 + self.mem[pos].to_i128();
+                self.rel_base  = i64::try_from(rel_base).map_err(|_| IntcodeError::Overflow { ip: self.ip })?;
                 self.ip       += 2;
             }
-            99 => return ProgramStatus::Finished,
-            _  => panic!("Invalid op code!"),
+            99 => return Ok(ProgramStatus::Finished),
+            _  => unreachable!(),
         }
-        ProgramStatus::Success
+        Ok(ProgramStatus::Success)
     }
 
-    fn get_pos(&mut self, mode: i64, pos: usize) -> usize {
-        let pos = match mode {
-            0 => self.mem[pos] as usize,
+    fn exec_extension(
+        &mut self,
+        op_code: i64,
+        instr:   i128,
+        ext:     Extension<W>,
+        input:   &mut VecDeque<W>,
+        output:  &mut VecDeque<W>,
+    ) -> Result<ProgramStatus, IntcodeError> {
+        let mut positions = Vec::with_capacity(ext.arity);
+        let mut modes     = instr / 100;
+        for i in 0..ext.arity {
+            positions.push(self.resolve((modes % 10) as i64, self.ip + 1 + i)?);
+            modes /= 10;
+        }
+        let mut args: Vec<_> = positions.iter().map(|&pos| self.mem[pos]).collect();
+        let status = (ext.handler)(&mut args, input, output)
+            .map_err(|msg| IntcodeError::Extension { ip: self.ip, op_code, msg })?;
+        if status == ProgramStatus::Success {
+            for &w in ext.writes {
+                self.write(positions[w], args[w])?;
+            }
+            self.ip += 1 + ext.arity;
+        }
+        Ok(status)
+    }
+
+    // Taint changes of the instruction at ip, None if it is invalid (which
+    // executing it reports).
+    fn taint_update(&mut self) -> Option<Update> {
+        self.taint.as_mut()?.reach(self.ip, self.rel_base);
+        let instr           = self.mem.get(self.ip).unwrap_or(W::ZERO).to_i128();
+        let op_code         = (instr % 100) as i64;
+        let custom          = self.extensions.contains_key(&op_code);
+        let (arity, writes) = match (self.extensions.get(&op_code), Op::from_code(op_code)) {
+            (Some(ext), _)   => (ext.arity, ext.writes),
+            (None, Some(op)) => (op.arity(), &[][..]),
+            (None, None)     => return None,
+        };
+        let mut params = Vec::with_capacity(arity);
+        let mut modes  = instr / 100;
+        for cell in self.ip + 1..self.ip + 1 + arity {
+            let mode = (modes % 10) as i64;
+            let pos  = match custom {
+                true  => self.resolve(mode, cell).ok()?,
+                false => self.get_pos(mode, cell).ok()?,
+            };
+            params.push(self.taint.as_ref()?.param(mode, cell, pos));
+            modes /= 10;
+        }
+        Some(self.taint.as_ref()?.update(self.ip, self.rel_base, op_code, &params, writes))
+    }
+
+    // Marks the instruction at ip as executed. In `CodeWrites::Error` mode,
+    // executing cells that were written since the program was loaded fails.
+    fn check_patched(&mut self, len: usize) -> Result<(), IntcodeError> {
+        if self.code_writes == CodeWrites::Ignore {
+            return Ok(());
+        }
+        if self.code_writes == CodeWrites::Error {
+            if let Some(write) = self.smc.patched(self.ip, len) {
+                return Err(IntcodeError::CodeWrite { ip: write.ip, addr: write.addr });
+            }
+        }
+        self.smc.mark_executed(self.ip, len);
+        Ok(())
+    }
+
+    fn jump(&mut self, target: W) -> Result<(), IntcodeError> {
+        let target = self.to_addr(target.to_i128())?;
+        let allowed = match self.protected.as_mut() {
+            Some(code) if !code.is_instr_start(target) => {
+                let mem: Vec<_> = self.mem.iter().map(decode_word).collect();
+                code.add_entry(&mem, target)
+            }
+            _ => true,
+        };
+        if !allowed {
+            let instr = self.instr_text();
+            return Err(IntcodeError::JumpIntoData { ip: self.ip, instr, target });
+        }
+        self.ip = target;
+        Ok(())
+    }
+
+    fn write(&mut self, pos: usize, val: W) -> Result<(), IntcodeError> {
+        if self.protected.as_ref().is_some_and(|code| code.is_instr_start(pos)) {
+            let instr = self.instr_text();
+            return Err(IntcodeError::ProtectedWrite { ip: self.ip, instr, addr: pos });
+        }
+        if self.code_writes != CodeWrites::Ignore {
+            if self.code_writes == CodeWrites::Error && self.smc.is_executed(pos) {
+                return Err(IntcodeError::CodeWrite { ip: self.ip, addr: pos });
+            }
+            let old = self.mem.get(pos).unwrap_or(W::ZERO);
+            self.smc.mark_written(CodeWrite { ip: self.ip, addr: pos, old, new: val });
+        }
+        self.mem.set(pos, val);
+        self.last_write = Some((pos, val));
+        Ok(())
+    }
+
+    // Disassembly of the current instruction for error messages.
+    fn instr_text(&self) -> String {
+        let words: Vec<_> = self.dump_range(self.ip..self.ip + 4);
+        let words: Vec<_> = words.into_iter().map(decode_word).collect();
+        match disasm::decode(&words, 0) {
+            Some(instr) => instr.to_string(),
+            None        => words[0].to_string(),
+        }
+    }
+
+    fn get_pos(&mut self, mode: i64, pos: usize) -> Result<usize, IntcodeError> {
+        if !self.isa.supports_mode(mode) {
+            return Err(if Isa::Day9.supports_mode(mode) {
+                IntcodeError::UnsupportedMode { ip: self.ip, mode, isa: self.isa }
+            } else {
+                IntcodeError::InvalidMode { ip: self.ip, mode }
+            });
+        }
+        self.resolve(mode, pos)
+    }
+
+    // Like `get_pos`, but accepts every mode of the complete instruction
+    // set, as custom op codes do.
+    fn resolve(&mut self, mode: i64, pos: usize) -> Result<usize, IntcodeError> {
+        // Parameters cut off by the end of memory read as zero.
+        let param = self.mem.get(pos).unwrap_or(W::ZERO).to_i128();
+        let pos   = match mode {
+            0 => self.to_addr(param)?,
             1 => pos,
-            2 => (self.rel_base + self.mem[pos]) as usize,
-            _ => panic!("Invalid parameter mode!"),
+            2 => self.to_addr(self.rel_base as i128 + param)?,
+            _ => return Err(IntcodeError::InvalidMode { ip: self.ip, mode }),
         };
         // Increase size if position is outside of the currently initialized memory.
-        if pos >= self.mem.len() {
-            for _ in self.mem.len()..=pos {
-                self.mem.push(0);
-            }
+        self.mem.resize(pos + 1);
+        Ok(pos)
+    }
+
+    fn to_addr(&self, addr: i128) -> Result<usize, IntcodeError> {
+        if addr < 0 || addr > i64::MAX as i128 {
+            Err(IntcodeError::InvalidAddress { ip: self.ip, addr })
+        } else {
+            Ok(addr as usize)
         }
-        pos
     }
 }
 
@@ -157,6 +605,14 @@ mod tests {
     use super::*;
 
     #[test]
+    fn program_parsing() {
+        assert_eq!(parse_program("1,0,0,3,99\n"), Ok(vec![1, 0, 0, 3, 99]));
+        assert_eq!(parse_program("104, -1, 99"), Ok(vec![104, -1, 99]));
+        assert!(parse_program("1,,99").is_err());
+    }
+
+    #[test]
+    #[allow(clippy::needless_borrow)]
     fn example_program1() {
         // Using position mode, consider whether the input is equal to 8;
         // output 1 (if it is) or 0 (if it is not).
@@ -242,4 +698,366 @@ mod tests {
             vec![1125899906842624]
         );
     }
+
+    #[test]
+    fn isa_profiles() {
+        // Day 2 programs only need the day 2 instruction set.
+        let prog = vec![1, 1, 1, 4, 99, 5, 6, 0, 99];
+        assert_eq!(IntcodeProg::try_exec_prog(&prog, Isa::Day2, Vec::new()), Ok(VecDeque::new()));
+
+        let prog = vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8];
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&prog, Isa::Day2, vec![8]),
+            Err(IntcodeError::UnsupportedOpCode { ip: 0, op_code: 3, isa: Isa::Day2 })
+        );
+        assert_eq!(IntcodeProg::try_exec_prog(&prog, Isa::Day5, vec![8]), Ok(VecDeque::from(vec![1])));
+
+        let prog = vec![3, 3, 1108, -1, 8, 3, 4, 3, 99];
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&prog, Isa::Day2, vec![8]),
+            Err(IntcodeError::UnsupportedOpCode { ip: 0, op_code: 3, isa: Isa::Day2 })
+        );
+
+        let prog = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&prog, Isa::Day5, Vec::new()),
+            Err(IntcodeError::UnsupportedOpCode { ip: 0, op_code: 9, isa: Isa::Day5 })
+        );
+        let prog = vec![1101, 1, 1, 0, 204, -1, 99];
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&prog, Isa::Day5, Vec::new()),
+            Err(IntcodeError::UnsupportedMode { ip: 4, mode: 2, isa: Isa::Day5 })
+        );
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&[42, 0, 0, 0], Isa::Day9, Vec::new()),
+            Err(IntcodeError::InvalidOpCode { ip: 0, op_code: 42 })
+        );
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&[301, 0, 0, 0, 99], Isa::Day9, Vec::new()),
+            Err(IntcodeError::InvalidMode { ip: 0, mode: 3 })
+        );
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&[3, 0, 99], Isa::Day9, Vec::new()),
+            Err(IntcodeError::MissingInput { ip: 0 })
+        );
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let prog = vec![1002, 5, 3, 6, 99, i64::MAX / 2 + 1, 0];
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&prog, Isa::Day9, Vec::new()),
+            Err(IntcodeError::Overflow { ip: 0 })
+        );
+
+        let mut wrap = IntcodeProg::new(&prog);
+        wrap.set_overflow(Overflow::Wrap);
+        assert_eq!(wrap.run(Vec::new()), Ok(VecDeque::new()));
+        assert_eq!(wrap.mem[6], (i64::MAX / 2 + 1).wrapping_mul(3));
+
+        let mut saturate = IntcodeProg::new(&prog);
+        saturate.set_overflow(Overflow::Saturate);
+        assert_eq!(saturate.run(Vec::new()), Ok(VecDeque::new()));
+        assert_eq!(saturate.mem[6], i64::MAX);
+    }
+
+    #[test]
+    fn wide_words() {
+        // Squares the input, which no longer fits into an i64.
+        let input: i128 = 1125899906842624;
+        let mut prog    = IntcodeProg::from_words(&[3, 0, 2, 0, 0, 0, 4, 0, 99], Isa::Day9);
+        assert_eq!(prog.run(vec![input]), Ok(VecDeque::from(vec![input * input])));
+
+        let mut prog = IntcodeProg::<i64>::from_words(&[3, 0, 2, 0, 0, 0, 4, 0, 99], Isa::Day9);
+        assert_eq!(prog.run(vec![input as i64]), Err(IntcodeError::Overflow { ip: 2 }));
+    }
+
+    #[test]
+    fn invalid_addresses() {
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&[1, -1, 0, 0, 99], Isa::Day9, Vec::new()),
+            Err(IntcodeError::InvalidAddress { ip: 0, addr: -1 })
+        );
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&[1105, 1, -5, 99], Isa::Day9, Vec::new()),
+            Err(IntcodeError::InvalidAddress { ip: 0, addr: -5 })
+        );
+    }
+
+    #[test]
+    fn truncated_programs() {
+        // Running off the end of memory and parameters cut off by it.
+        let run = |prog: &[i64]| IntcodeProg::try_exec_prog(prog, Isa::default(), vec![]);
+        assert_eq!(run(&[1105, 1, 100, 99]), Err(IntcodeError::InvalidOpCode { ip: 100, op_code: 0 }));
+        assert_eq!(run(&[1]), Err(IntcodeError::InvalidOpCode { ip: 4, op_code: 0 }));
+        assert_eq!(run(&[1101, 1]), Err(IntcodeError::InvalidOpCode { ip: 4, op_code: 0 }));
+        assert_eq!(run(&[1101, 1, 1, 0]), Err(IntcodeError::InvalidOpCode { ip: 4, op_code: 0 }));
+    }
+
+    #[test]
+    fn negative_relative_base() {
+        // Only the effective address [rb+5] = [4] has to be valid.
+        assert_eq!(IntcodeProg::exec_prog(&[109, -1, 204, 5, 99, 7, 8], Vec::new()), vec![99]);
+        assert_eq!(
+            IntcodeProg::try_exec_prog(&[109, -1, 204, 0, 99], Isa::Day9, Vec::new()),
+            Err(IntcodeError::InvalidAddress { ip: 2, addr: -1 })
+        );
+    }
+
+    fn debug_print(
+        args:   &mut [i64],
+        _input: &mut VecDeque<i64>,
+        output: &mut VecDeque<i64>,
+    ) -> Result<ProgramStatus, String> {
+        output.push_back(-args[0]);
+        Ok(ProgramStatus::Success)
+    }
+
+    fn host_gcd(
+        args:    &mut [i64],
+        _input:  &mut VecDeque<i64>,
+        _output: &mut VecDeque<i64>,
+    ) -> Result<ProgramStatus, String> {
+        if args[0] <= 0 || args[1] <= 0 {
+            return Err(String::from("gcd needs positive arguments"));
+        }
+        let (mut a, mut b) = (args[0], args[1]);
+        while b != 0 {
+            let t = b;
+            b     = a % b;
+            a     = t;
+        }
+        args[2] = a;
+        Ok(ProgramStatus::Success)
+    }
+
+    #[test]
+    fn custom_op_codes() {
+        // gcd(input, 84) is written to position 12 and output by the debug op.
+        let prog = vec![3, 11, 1051, 11, 84, 12, 50, 12, 4, 12, 99, 0, 0];
+        let mut machine = IntcodeProg::with_isa(&prog, Isa::Day5);
+        machine.register_op(50, Extension::new(1, &[], debug_print)).unwrap();
+        machine.register_op(51, Extension::new(3, &[2], host_gcd)).unwrap();
+        assert_eq!(machine.clone().run(vec![60]), Ok(VecDeque::from(vec![-12, 12])));
+        assert_eq!(
+            machine.run(vec![-60]),
+            Err(IntcodeError::Extension {
+                ip:      2,
+                op_code: 51,
+                msg:     String::from("gcd needs positive arguments"),
+            })
+        );
+
+        let mut machine = IntcodeProg::new(&prog);
+        assert_eq!(
+            machine.register_op(9, Extension::new(1, &[], debug_print)),
+            Err(IntcodeError::ReservedOpCode { op_code: 9 })
+        );
+        assert_eq!(
+            machine.register_op(52, Extension::new(1, &[1], debug_print)),
+            Err(IntcodeError::InvalidExtension { op_code: 52 })
+        );
+        assert_eq!(
+            machine.run(vec![60]),
+            Err(IntcodeError::InvalidOpCode { ip: 2, op_code: 51 })
+        );
+
+        // Custom instructions may use all modes, even under the day 2 ISA.
+        let mut machine = IntcodeProg::with_isa(&[150, 7, 250, 4, 99], Isa::Day2);
+        machine.register_op(50, Extension::new(1, &[], debug_print)).unwrap();
+        assert_eq!(machine.run(vec![]), Ok(VecDeque::from(vec![-7, -99])));
+    }
+
+    #[test]
+    fn forking() {
+        // Echoes its input through position 600, forever.
+        let mut prog = vec![3, 600, 4, 600, 1105, 1, 0];
+        prog.resize(1000, 0);
+        let mut parent = IntcodeProg::new(&prog);
+        let mut input  = VecDeque::from(vec![1]);
+        let mut output = VecDeque::new();
+        while parent.exec_instr(&mut input, &mut output) == ProgramStatus::Success {}
+
+        let mut child = parent.fork();
+        assert_eq!(child.mem().shared_pages(parent.mem()), 4);
+        input.push_back(2);
+        while child.exec_instr(&mut input, &mut output) == ProgramStatus::Success {}
+        assert_eq!(output, vec![1, 2]);
+        assert_eq!(parent.mem()[600], 1);
+        assert_eq!(child.mem()[600], 2);
+        assert_eq!(child.mem().shared_pages(parent.mem()), 3);
+    }
+
+    #[test]
+    fn state_hashing() {
+        use std::collections::HashSet;
+
+        // Counts down from the input, outputting every value.
+        let prog    = vec![3, 11, 4, 11, 1001, 11, -1, 11, 1005, 11, 2, 0, 0];
+        let mut a   = IntcodeProg::new(&prog);
+        let mut b   = IntcodeProg::new(&prog);
+        let mut out = VecDeque::new();
+        a.exec_instr(&mut VecDeque::from(vec![3]), &mut out);
+        b.exec_instr(&mut VecDeque::from(vec![2]), &mut out);
+        assert_ne!(a, b);
+
+        let mut seen = HashSet::new();
+        seen.insert(b.clone());
+        for _ in 0..3 {
+            a.exec_instr(&mut VecDeque::new(), &mut out);
+        }
+        assert_eq!(a, b);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert!(seen.contains(&a));
+        assert!(!seen.contains(&IntcodeProg::new(&prog)));
+
+        // Trailing zeros do not matter.
+        let mut padded = prog.clone();
+        padded.resize(1000, 0);
+        assert_eq!(IntcodeProg::new(&padded), IntcodeProg::new(&prog));
+    }
+
+    #[test]
+    fn introspection() {
+        let mut prog = IntcodeProg::new(&[109, 7, 204, 3, 99]);
+        assert_eq!(prog.poke(10, 42), Ok(()));
+        assert_eq!(prog.mem().len(), 11);
+        assert_eq!(prog.peek(10), Ok(42));
+        assert_eq!(prog.peek(1000), Ok(0));
+        assert_eq!(prog.peek(-1), Err(IntcodeError::InvalidAddress { ip: 0, addr: -1 }));
+        assert_eq!(prog.poke(-5, 1), Err(IntcodeError::InvalidAddress { ip: 0, addr: -5 }));
+        assert_eq!(prog.dump_range(3..6), vec![3, 99, 0]);
+
+        let mut output = VecDeque::new();
+        prog.exec_instr(&mut VecDeque::new(), &mut output);
+        assert_eq!((prog.ip(), prog.rel_base()), (2, 7));
+        prog.exec_instr(&mut VecDeque::new(), &mut output);
+        assert_eq!(output, vec![42]);
+        assert_eq!(prog.mem().len(), 11);
+    }
+
+    #[test]
+    fn reset() {
+        // Doubles its input in position 11, behind the program.
+        let prog        = vec![109, 3, 3, 11, 1002, 11, 2, 11, 204, 8, 99];
+        let mut machine = IntcodeProg::new(&prog);
+        assert_eq!(machine.run(vec![21]), Ok(VecDeque::from(vec![42])));
+        assert_eq!((machine.rel_base(), machine.mem().len()), (3, 12));
+        machine.reset();
+        assert_eq!(machine, IntcodeProg::new(&prog));
+        assert_eq!((machine.rel_base(), machine.mem().len()), (0, 11));
+        assert_eq!(machine.run(vec![4]), Ok(VecDeque::from(vec![8])));
+        assert_eq!(machine.peek(11), Ok(8));
+    }
+
+    #[test]
+    fn code_writes() {
+        // Day 2 example, both instructions patch code.
+        let prog        = vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50];
+        let mut machine = IntcodeProg::new(&prog);
+        machine.set_code_writes(CodeWrites::Record);
+        machine.run(vec![]).unwrap();
+        assert_eq!(machine.code_writes(), &[
+            CodeWrite { ip: 0, addr: 3, old: 3, new: 70 },
+            CodeWrite { ip: 4, addr: 0, old: 1, new: 3500 },
+        ]);
+        assert_eq!(machine.code_writes()[1].to_string(), "ip 4 wrote 3500 to 0 (was 1)");
+        assert_eq!(machine.self_modified(), vec![0, 3]);
+        machine.reset();
+        assert!(machine.code_writes().is_empty());
+
+        machine.set_code_writes(CodeWrites::Error);
+        assert_eq!(machine.run(vec![]), Err(IntcodeError::CodeWrite { ip: 0, addr: 3 }));
+
+        // Writes behind the code are data.
+        let mut machine = IntcodeProg::new(&[3, 11, 1002, 11, 2, 11, 4, 11, 99]);
+        machine.set_code_writes(CodeWrites::Error);
+        assert_eq!(machine.run(vec![21]), Ok(VecDeque::from(vec![42])));
+        assert!(machine.self_modified().is_empty());
+
+        // Forward patch: add 100, 4, [4] turns the data at 4 into out 7.
+        let prog        = [1101, 100, 4, 4, 0, 7, 99];
+        let mut machine = IntcodeProg::new(&prog);
+        machine.set_code_writes(CodeWrites::Record);
+        assert_eq!(machine.run(vec![]), Ok(VecDeque::from(vec![7])));
+        assert_eq!(machine.code_writes(), &[CodeWrite { ip: 0, addr: 4, old: 0, new: 104 }]);
+        assert_eq!(machine.self_modified(), vec![4]);
+        machine.reset();
+        machine.set_code_writes(CodeWrites::Error);
+        assert_eq!(machine.run(vec![]), Err(IntcodeError::CodeWrite { ip: 0, addr: 4 }));
+        assert_eq!(machine.ip(), 4);
+    }
+
+    #[test]
+    fn protection() {
+        let mut machine = IntcodeProg::new(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        machine.protect_code();
+        assert_eq!(machine.code_region().unwrap().cells(), 9);
+        let err = machine.run(vec![]).unwrap_err();
+        // Patching the parameter at 3 is fine, overwriting the op code at 0 is not.
+        assert_eq!(
+            err.to_string(),
+            "Instruction 'mul [3], [11], [0]' at position 4 writes to protected code at address 0!"
+        );
+        machine.reset();
+        machine.unprotect_code();
+        assert_eq!(machine.run(vec![]), Ok(VecDeque::new()));
+
+        // jz 0, [4] jumps into its own parameters.
+        let mut machine = IntcodeProg::new(&[106, 0, 4, 99, 2]);
+        machine.protect_code();
+        assert_eq!(
+            machine.run(vec![]),
+            Err(IntcodeError::JumpIntoData { ip: 0, instr: String::from("jz 0, [4]"), target: 2 })
+        );
+
+        // jz 0, [7] jumps to data that happens to decode as out 5.
+        let mut machine = IntcodeProg::new(&[106, 0, 7, 99, 104, 5, 99, 4]);
+        machine.protect_code();
+        assert_eq!(
+            machine.run(vec![]),
+            Err(IntcodeError::JumpIntoData { ip: 0, instr: String::from("jz 0, [7]"), target: 4 })
+        );
+        assert_eq!(machine.code_region().unwrap().cells(), 3);
+
+        // Only op codes are protected: add 100, 4, [5] turns the parameter
+        // of the following out 0 into 104.
+        let mut machine = IntcodeProg::new(&[1101, 100, 4, 5, 104, 0, 99]);
+        machine.protect_code();
+        assert_eq!(machine.run(vec![]), Ok(VecDeque::from(vec![104])));
+    }
+
+    #[test]
+    fn taint() {
+        // in [30]; in [31]; in [32]; out [30] + [31]; out 7; jz [32], 19;
+        // out 5; out 6
+        let prog = [
+            3, 30, 3, 31, 3, 32, 1, 30, 31, 33, 4, 33, 104, 7, 1006, 32, 19, 104, 5, 104, 6, 99,
+        ];
+        let labels = |machine: &IntcodeProg| -> Vec<String> {
+            machine.taint().unwrap().outputs().iter().map(taint::ranges).collect()
+        };
+        let mut machine = IntcodeProg::new(&prog);
+        machine.enable_taint();
+        assert_eq!(machine.run(vec![1, 2, 0]), Ok(VecDeque::from(vec![3, 7, 6])));
+        assert_eq!(labels(&machine), vec!["0-1", "", ""]);
+        assert_eq!(machine.taint().unwrap().cell(33), [0, 1].iter().copied().collect());
+
+        // Only the output skipped by the jump depends on its condition, the
+        // branches join again at 19.
+        machine.reset();
+        assert_eq!(machine.run(vec![1, 2, 3, 4]), Ok(VecDeque::from(vec![3, 7, 5, 6])));
+        assert_eq!(labels(&machine), vec!["0-1", "", "2", ""]);
+        assert!(machine.taint().unwrap().unused().is_empty());
+
+        // A tainted pointer taints what is read through it: in [3]; out [7]
+        let mut machine = IntcodeProg::new(&[3, 3, 4, 0, 99, 0, 0, 42]);
+        machine.enable_taint();
+        assert_eq!(machine.run(vec![7]), Ok(VecDeque::from(vec![42])));
+        assert_eq!(labels(&machine), vec!["0"]);
+    }
 }
@@ -0,0 +1,412 @@
+use std::collections::VecDeque;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::process;
+
+use intcode::compiler;
+use intcode::debug::Debugger;
+use intcode::decompile;
+use intcode::disasm;
+use intcode::image;
+use intcode::object;
+use intcode::object::Object;
+use intcode::strings;
+use intcode::symbols::SymbolMap;
+use intcode::taint;
+use intcode::CodeWrites;
+use intcode::testcase;
+use intcode::trace;
+use intcode::trace::TraceWriter;
+use intcode::IntcodeProg;
+use intcode::ProgramStatus as IPS;
+
+const USAGE: &str = "\
+Usage: intcode <command> <program> [options]
+       intcode pack <program> <image>
+       intcode test <file or directory>
+       intcode diff <trace> <trace> [options]
+       intcode compile <source>
+       intcode link <program> <object>...
+
+Commands:
+    run        Execute the program
+    disasm     Print a disassembly listing
+    decompile  Print structured pseudocode
+    pack       Write the program as a binary image (see the image module)
+    strings    List strings and data tables in the program (see the strings module)
+    debug      Serve the debugging protocol (see the debug module)
+    test       Run test case files (see the testcase module)
+    diff       Report the first difference between two execution traces
+    compile    Compile a source file to an Intcode program (see the compiler module)
+    link       Link object files into a program with its symbol file next to
+               it (see the object module)
+
+Options for run:
+    --input <csv>         Comma-separated input values (text in ASCII mode)
+    --input-file <file>   Read the input values (or text) from a file
+    --ascii               Exchange text instead of numbers
+    --max-steps <n>       Abort after executing n instructions
+    --dump                Print the final memory
+    --peek <csv>          Print the final values of the given addresses
+    --trace <file>        Write a binary execution trace (see the trace module)
+    --code-writes <mode>  Report writes into executed code (record) or
+                          stop at the first one (error)
+    --protect             Make the op codes of the reachable code read-only
+                          (see the protect module)
+    --taint               Report the inputs each output depends on (see the
+                          taint module)
+
+Programs are read from comma-separated text or binary images.
+Without --input or --input-file, input is read interactively from stdin.
+disasm, decompile and debug use the symbol file next to the program
+(e.g. input.sym for input.txt) if there is one.
+
+Options for debug:
+    --tcp <addr>          Listen on a TCP address (default 127.0.0.1:7019)
+    --unix <path>         Listen on a Unix domain socket instead
+
+Options for strings:
+    --min <n>             Minimum number of characters (default 4), runs
+                          without a terminator need twice as many and
+                          tables 4 times as many cells
+    --symbols             Print the strings as symbol file lines
+
+Options for diff:
+    --context <n>         Number of steps shown around the difference (default 5)
+    --symbols <file>      Show addresses with the names from a symbol file";
+
+#[derive(Default)]
+struct Options {
+    input:       Option<String>,
+    ascii:       bool,
+    max_steps:   Option<u64>,
+    dump:        bool,
+    peek:        Vec<usize>,
+    trace:       Option<String>,
+    code_writes: CodeWrites,
+    protect:     bool,
+    taint:       bool,
+}
+
+fn main() {
+    if let Err(e) = run_cli(env::args().skip(1).collect()) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run_cli(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let commands = ["run", "disasm", "decompile", "pack", "strings", "debug", "test", "diff", "compile", "link"];
+    if args.len() < 2 || !commands.contains(&args[0].as_str()) {
+        return Err(USAGE.into());
+    }
+    if args[0] == "test" {
+        return run_tests(&args[1]);
+    } else if args[0] == "diff" {
+        return diff(&args[1..]);
+    } else if args[0] == "compile" {
+        let prog = compiler::compile(&fs::read_to_string(&args[1])?)?;
+        println!("{}", join_values(&prog));
+        return Ok(());
+    } else if args[0] == "link" {
+        if args.len() < 3 {
+            return Err(USAGE.into());
+        }
+        let objects = args[2..].iter().map(Object::load).collect::<Result<Vec<_>, _>>()?;
+        let linked  = object::link(&objects)?;
+        fs::write(&args[1], join_values(&linked.image) + "\n")?;
+        fs::write(SymbolMap::sidecar_path(&args[1]), linked.symbols.to_string())?;
+        return Ok(());
+    }
+    let prog    = intcode::load_program(&args[1])?;
+    let symbols = SymbolMap::load_sidecar(&args[1])?;
+    match args[0].as_str() {
+        "run"       => run(&prog, parse_options(&args[2..])?)?,
+        "disasm"    => print!("{}", disasm::listing_with(&prog, &symbols)),
+        "decompile" => print!("{}", decompile::decompile_with(&prog, &symbols)),
+        "pack"      => match args.get(2) {
+            Some(path) => image::write(&mut File::create(path)?, &prog)?,
+            None       => return Err(USAGE.into()),
+        },
+        "strings"   => list_strings(&prog, &args[2..])?,
+        "debug"     => debug(&prog, symbols, &args[2..])?,
+        _           => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn parse_options(args: &[String]) -> Result<Options, Box<dyn Error>> {
+    let mut opts = Options::default();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--input"       => opts.input = Some(value()?.clone()),
+            "--input-file"  => opts.input = Some(fs::read_to_string(value()?)?),
+            "--ascii"       => opts.ascii = true,
+            "--max-steps"   => opts.max_steps = Some(value()?.parse()?),
+            "--dump"        => opts.dump = true,
+            "--peek"        => opts.peek = parse_values(value()?)?,
+            "--trace"       => opts.trace = Some(value()?.clone()),
+            "--code-writes" => {
+                opts.code_writes = match value()?.as_str() {
+                    "record" => CodeWrites::Record,
+                    "error"  => CodeWrites::Error,
+                    mode     => return Err(format!("invalid mode {} for --code-writes", mode).into()),
+                }
+            }
+            "--protect"     => opts.protect = true,
+            "--taint"       => opts.taint = true,
+            _               => return Err(format!("unknown option {}\n\n{}", arg, USAGE).into()),
+        }
+    }
+    Ok(opts)
+}
+
+fn list_strings(prog: &[i64], args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (mut min, mut symbols) = (4, false);
+    let mut iter               = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--min"     => min = iter.next().ok_or("missing value for --min")?.parse()?,
+            "--symbols" => symbols = true,
+            _           => return Err(format!("unknown option {}\n\n{}", arg, USAGE).into()),
+        }
+    }
+    let found = strings::strings(prog, min);
+    for text in found.iter() {
+        if symbols {
+            let sym = text.symbol();
+            println!("{} {} {} ; {}", sym.addr, sym.name, sym.kind, sym.comment.unwrap_or_default());
+        } else {
+            println!("{}", text);
+        }
+    }
+    if !symbols {
+        for table in strings::tables(prog, 4 * min, &found) {
+            println!("{}", table);
+        }
+    }
+    Ok(())
+}
+
+fn debug(prog: &[i64], symbols: SymbolMap, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut dbg = Debugger::new(IntcodeProg::new(prog));
+    dbg.symbols = symbols;
+    match (args.first().map(|s| s.as_str()), args.get(1)) {
+        (None, _)                   => dbg.listen_tcp("127.0.0.1:7019")?,
+        (Some("--tcp"), Some(addr)) => dbg.listen_tcp(addr.as_str())?,
+        #[cfg(unix)]
+        (Some("--unix"), Some(path)) => dbg.listen_unix(path)?,
+        _ => return Err(USAGE.into()),
+    }
+    Ok(())
+}
+
+fn run_tests(path: &str) -> Result<(), Box<dyn Error>> {
+    let (mut passed, mut failed) = (0, 0);
+    for file in testcase::discover(path)? {
+        for outcome in testcase::run_file(&file)? {
+            match outcome.result {
+                Ok(()) => {
+                    println!("ok     {}: {}", file.display(), outcome.name);
+                    passed += 1;
+                }
+                Err(msg) => {
+                    println!("FAILED {}:{}: {}: {}", file.display(), outcome.line, outcome.name, msg);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        return Err(format!("{} test cases failed", failed).into());
+    }
+    Ok(())
+}
+
+fn diff(args: &[String]) -> Result<(), Box<dyn Error>> {
+    if args.len() < 2 {
+        return Err(USAGE.into());
+    }
+    let mut context = 5;
+    let mut symbols = SymbolMap::default();
+    let mut iter    = args[2..].iter();
+    while let Some(arg) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--context" => context = value()?.parse()?,
+            "--symbols" => symbols = SymbolMap::load(value()?)?,
+            _           => return Err(format!("unknown option {}\n\n{}", arg, USAGE).into()),
+        }
+    }
+    let left  = BufReader::new(File::open(&args[0])?);
+    let right = BufReader::new(File::open(&args[1])?);
+    match trace::diff(left, right, context)? {
+        Some(divergence) => {
+            print!("{}", divergence.report(&symbols));
+            Err("traces differ".into())
+        }
+        None => {
+            println!("traces are identical");
+            Ok(())
+        }
+    }
+}
+
+fn parse_values<T: std::str::FromStr>(text: &str) -> Result<Vec<T>, Box<dyn Error>>
+where
+    T::Err: Error + 'static,
+{
+    let mut values = Vec::new();
+    for s in text.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()) {
+        values.push(s.parse()?);
+    }
+    Ok(values)
+}
+
+fn join_values(values: &[i64]) -> String {
+    values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn encode_input(text: &str, ascii: bool) -> Result<Vec<i64>, Box<dyn Error>> {
+    if ascii {
+        let mut values: Vec<_> = text.chars().map(|c| c as i64).collect();
+        if values.last() != Some(&10) {
+            values.push(10);
+        }
+        Ok(values)
+    } else {
+        parse_values(text)
+    }
+}
+
+fn run(prog: &[i64], opts: Options) -> Result<(), Box<dyn Error>> {
+    let mut prog   = IntcodeProg::new(prog);
+    prog.set_code_writes(opts.code_writes);
+    if opts.protect {
+        prog.protect_code();
+    }
+    if opts.taint {
+        prog.enable_taint();
+    }
+    let mut input  = VecDeque::new();
+    let mut output = VecDeque::new();
+    let mut steps  = 0;
+    let mut values = Vec::new();
+    let mut trace  = match &opts.trace {
+        Some(path) => Some(TraceWriter::new(BufWriter::new(File::create(path)?))?),
+        None       => None,
+    };
+    if let Some(text) = &opts.input {
+        input.extend(encode_input(text, opts.ascii)?);
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    loop {
+        if opts.max_steps.is_some_and(|max| steps >= max) {
+            return Err(format!("step limit of {} reached", steps).into());
+        }
+        let status = match trace.as_mut() {
+            Some(writer) => {
+                let (status, step) = trace::exec_traced(&mut prog, &mut input, &mut output)?;
+                if let Some(step) = step {
+                    writer.write_step(&step)?;
+                }
+                status
+            }
+            None => prog.try_exec_instr(&mut input, &mut output)?,
+        };
+        steps += 1;
+        while let Some(val) = output.pop_front() {
+            if opts.taint {
+                values.push(val);
+            }
+            if opts.ascii && (0..128).contains(&val) {
+                write!(stdout, "{}", val as u8 as char)?;
+            } else {
+                writeln!(stdout, "{}", val)?;
+            }
+        }
+        match status {
+            IPS::Success  => (),
+            IPS::Finished => break,
+            IPS::WaitingForInput => {
+                if opts.input.is_some() {
+                    return Err("program is waiting for more input".into());
+                }
+                stdout.flush()?;
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line)? == 0 {
+                    return Err("unexpected end of input".into());
+                }
+                input.extend(encode_input(line.trim_end_matches(&['\r', '\n'][..]), opts.ascii)?);
+            }
+        }
+    }
+
+    if let Some(writer) = trace.as_mut() {
+        writer.flush()?;
+    }
+    if opts.code_writes == CodeWrites::Record {
+        for write in prog.code_writes() {
+            eprintln!("{}", write);
+        }
+        eprintln!(
+            "{} writes into code, {} self-modified cells",
+            prog.code_writes().len(),
+            prog.self_modified().len()
+        );
+    }
+    if let Some(taint) = prog.taint() {
+        for (i, (val, labels)) in values.iter().zip(taint.outputs()).enumerate() {
+            eprintln!("output {} = {}: inputs {}", i, val, taint::ranges(labels));
+        }
+        eprintln!("{} inputs, unused: {}", taint.inputs(), taint::ranges(&taint.unused()));
+    }
+    if opts.dump {
+        let words: Vec<_> = prog.mem().iter().map(|v| v.to_string()).collect();
+        writeln!(stdout, "{}", words.join(","))?;
+    }
+    for &addr in opts.peek.iter() {
+        let val = prog.mem().get(addr).unwrap_or(0);
+        writeln!(stdout, "[{}] = {}", addr, val)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_encoding() {
+        assert_eq!(encode_input("1, -2,3\n4", false).unwrap(), vec![1, -2, 3, 4]);
+        assert_eq!(encode_input("NOT A J", true).unwrap().len(), 8);
+        assert_eq!(encode_input("WALK\n", true).unwrap(), vec![87, 65, 76, 75, 10]);
+        assert!(encode_input("1,x", false).is_err());
+    }
+
+    #[test]
+    fn option_parsing() {
+        let args: Vec<_> = ["--ascii", "--max-steps", "100", "--peek", "0,4"]
+            .iter()
+            .chain(["--code-writes", "error"].iter())
+            .map(|s| s.to_string())
+            .collect();
+        let opts = parse_options(&args).unwrap();
+        assert!(opts.ascii && !opts.dump);
+        assert_eq!(opts.max_steps, Some(100));
+        assert_eq!(opts.peek, vec![0, 4]);
+        assert_eq!(opts.code_writes, CodeWrites::Error);
+        assert!(parse_options(&args[..2]).is_err());
+    }
+}
@@ -0,0 +1,253 @@
+// Paged copy-on-write memory.
+//
+// Cloning a `Memory` only copies the page table. Pages are shared between
+// the clones until one of them writes to a page, which then gets a private
+// copy. This keeps forking machines cheap, e.g. for a breadth-first search
+// where every state only differs in a few cells.
+//
+// A fingerprint of the contents is kept up to date on every write. It is
+// the wrapping sum of a hash of each (address, value) pair, where zero cells
+// contribute nothing, so memories that only differ in trailing zeros have
+// the same fingerprint.
+//
+// Pages written since the memory was created (or last restored) are marked
+// as dirty, so that restoring the original image only has to copy those.
+
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::ops::Index;
+use std::sync::Arc;
+
+use crate::Word;
+
+const PAGE_BITS: usize = 8;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const PAGE_MASK: usize = PAGE_SIZE - 1;
+
+#[derive(Debug, Clone)]
+pub struct Memory<W> {
+    pages:       Vec<Arc<Vec<W>>>,
+    dirty:       Vec<bool>,
+    len:         usize,
+    fingerprint: u64,
+}
+
+fn mix<W: Word>(addr: usize, val: W) -> u64 {
+    if val == W::ZERO {
+        return 0;
+    }
+    let val   = val.to_i128() as u128;
+    let mut h = (addr as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ (val as u64) ^ ((val >> 64) as u64);
+    // Finalizer of splitmix64.
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+impl<W: Word> Memory<W> {
+    pub fn from_slice(words: &[W]) -> Memory<W> {
+        let pages = words
+            .chunks(PAGE_SIZE)
+            .map(|chunk| {
+                let mut page = chunk.to_vec();
+                page.resize(PAGE_SIZE, W::ZERO);
+                Arc::new(page)
+            })
+            .collect::<Vec<_>>();
+        let dirty       = vec![false; pages.len()];
+        let fingerprint = words
+            .iter()
+            .enumerate()
+            .fold(0u64, |fp, (addr, &val)| fp.wrapping_add(mix(addr, val)));
+        Memory { pages, dirty, len: words.len(), fingerprint }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, addr: usize) -> Option<W> {
+        if addr < self.len {
+            Some(self.pages[addr >> PAGE_BITS][addr & PAGE_MASK])
+        } else {
+            None
+        }
+    }
+
+    // Writes a cell, growing the memory if necessary.
+    pub fn set(&mut self, addr: usize, val: W) {
+        if addr >= self.len {
+            self.resize(addr + 1);
+        }
+        self.dirty[addr >> PAGE_BITS] = true;
+        let cell = &mut Arc::make_mut(&mut self.pages[addr >> PAGE_BITS])[addr & PAGE_MASK];
+        self.fingerprint = self
+            .fingerprint
+            .wrapping_sub(mix(addr, *cell))
+            .wrapping_add(mix(addr, val));
+        *cell = val;
+    }
+
+    // Grows the memory with zeros. All new pages share a single zero page
+    // until they are written to.
+    pub fn resize(&mut self, len: usize) {
+        if len <= self.len {
+            return;
+        }
+        let pages = (len + PAGE_MASK) >> PAGE_BITS;
+        if pages > self.pages.len() {
+            let zero = Arc::new(vec![W::ZERO; PAGE_SIZE]);
+            self.pages.resize(pages, zero);
+            self.dirty.resize(pages, false);
+        }
+        self.len = len;
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = W> + '_ {
+        self.pages.iter().flat_map(|page| page.iter().copied()).take(self.len)
+    }
+
+    pub fn to_vec(&self) -> Vec<W> {
+        self.iter().collect()
+    }
+
+    // Restores the contents of `image`, which must be the memory this one
+    // was created from (directly or through clones). Only dirty pages are
+    // copied, into the existing allocation if it is not shared.
+    pub fn restore(&mut self, image: &Memory<W>) {
+        self.pages.truncate(image.pages.len());
+        self.dirty.truncate(image.pages.len());
+        for (i, src) in image.pages.iter().enumerate() {
+            if i >= self.pages.len() {
+                self.pages.push(src.clone());
+                self.dirty.push(false);
+            } else if self.dirty[i] && !Arc::ptr_eq(&self.pages[i], src) {
+                match Arc::get_mut(&mut self.pages[i]) {
+                    Some(page) => page.copy_from_slice(src),
+                    None       => self.pages[i] = src.clone(),
+                }
+            }
+            self.dirty[i] = false;
+        }
+        self.len         = image.len;
+        self.fingerprint = image.fingerprint;
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    // Number of pages that are physically shared with another memory.
+    pub fn shared_pages(&self, other: &Memory<W>) -> usize {
+        self.pages
+            .iter()
+            .zip(other.pages.iter())
+            .filter(|(a, b)| Arc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+// Memories are equal if their contents only differ in trailing zeros.
+impl<W: Word> PartialEq for Memory<W> {
+    fn eq(&self, other: &Memory<W>) -> bool {
+        if self.fingerprint != other.fingerprint {
+            return false;
+        }
+        let pages = self.pages.len().max(other.pages.len());
+        (0..pages).all(|i| match (self.pages.get(i), other.pages.get(i)) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b) || a == b,
+            (Some(p), None) | (None, Some(p)) => p.iter().all(|&v| v == W::ZERO),
+            (None, None) => unreachable!(),
+        })
+    }
+}
+
+impl<W: Word> Eq for Memory<W> {}
+
+impl<W: Word> Hash for Memory<W> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fingerprint.hash(state);
+    }
+}
+
+impl<W: Word> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, addr: usize) -> &W {
+        assert!(addr < self.len, "address {} out of bounds", addr);
+        &self.pages[addr >> PAGE_BITS][addr & PAGE_MASK]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_on_write() {
+        let words: Vec<i64> = (0..1000).collect();
+        let mut a = Memory::from_slice(&words);
+        assert_eq!(a.len(), 1000);
+        assert_eq!(a.get(999), Some(999));
+        assert_eq!(a.get(1000), None);
+
+        let mut b = a.clone();
+        b.set(300, -1);
+        assert_eq!(a[300], 300);
+        assert_eq!(b[300], -1);
+        assert_eq!(a.shared_pages(&b), 3);
+
+        a.set(5000, 7);
+        assert_eq!(a.len(), 5001);
+        assert_eq!(a[4999], 0);
+        assert_eq!(a.iter().filter(|&v| v == 7).count(), 2);
+        assert_eq!(&a.to_vec()[..1000], &words[..]);
+        assert_eq!(b.to_vec().len(), 1000);
+    }
+
+    #[test]
+    fn restore() {
+        let words: Vec<i64> = (0..1000).collect();
+        let image = Memory::from_slice(&words);
+        let mut a = image.clone();
+        a.set(300, -1);
+        a.set(2000, 5);
+        let page = Arc::as_ptr(&a.pages[1]);
+        a.restore(&image);
+        assert_eq!(a, image);
+        assert_eq!(a.len(), 1000);
+        assert_eq!(a.fingerprint(), image.fingerprint());
+
+        // The private copy of the dirty page is reused.
+        assert_eq!(Arc::as_ptr(&a.pages[1]), page);
+        a.set(300, -2);
+        assert_eq!(Arc::as_ptr(&a.pages[1]), page);
+        assert_eq!(image[300], 300);
+        a.restore(&image);
+        assert_eq!(a.to_vec(), words);
+    }
+
+    #[test]
+    fn fingerprints() {
+        let mut a = Memory::from_slice(&[1, 2, 3]);
+        let mut b = Memory::from_slice(&[1, 2, 3, 0, 0]);
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_eq!(a, b);
+
+        b.set(700, 4);
+        assert_ne!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a, b);
+        b.set(700, 0);
+        assert_eq!(a, b);
+
+        // Same values at swapped addresses.
+        a.set(0, 2);
+        a.set(1, 1);
+        assert_ne!(a.fingerprint(), b.fingerprint());
+        assert_eq!(a.fingerprint(), Memory::from_slice(&[2, 1, 3]).fingerprint());
+    }
+}
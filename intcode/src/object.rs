@@ -0,0 +1,228 @@
+// Relocatable Intcode modules and a linker for them.
+//
+// An object file holds the code of one module, assembled as if it was
+// loaded at address 0:
+//
+//     # Counts how often it was called.
+//     module: counter
+//     code: 1001,11,1,11, 21001,11,0,1, 2105,1,0, 0
+//     reloc: 1, 3, 5
+//     export: 0 next code
+//     export: 11 count word ; number of calls
+//
+// `reloc` lists the words holding an address inside the module (position
+// mode parameters and jump targets), which are moved with the module.
+// `export` lines define symbols with the syntax of symbol files (see the
+// symbols module), which have to lie inside of the module. `import: <index>
+// <name>` adds the address of a symbol exported by another module to the
+// word at the index, so the word holds the offset from that symbol.
+// Repeated lines are appended; blank lines and lines starting with `#` are
+// ignored.
+//
+// The linker places the modules one after the other, starting at address
+// 0, so execution starts with the first module. Words that overflow when
+// relocated or imported are link errors.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::symbols::parse_symbol;
+use crate::symbols::Symbol;
+use crate::symbols::SymbolMap;
+
+// File extension of object files.
+pub const EXTENSION: &str = "ico";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Object {
+    pub name:    String,
+    pub code:    Vec<i64>,
+    pub relocs:  Vec<usize>,
+    pub exports: Vec<Symbol>,
+    pub imports: Vec<(usize, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linked {
+    pub image:   Vec<i64>,
+    // Exports of all modules at their final addresses.
+    pub symbols: SymbolMap,
+}
+
+fn parse_values<T: std::str::FromStr>(text: &str) -> Result<Vec<T>, String> {
+    text.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| format!("invalid value {}", s)))
+        .collect()
+}
+
+impl Object {
+    pub fn parse(text: &str) -> Result<Object, String> {
+        let mut obj = Object::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", i + 1, msg);
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| err(String::from("expected key: value")))?;
+            let value = value.trim();
+            match key.trim() {
+                "module" => obj.name = value.to_string(),
+                "code"   => obj.code.extend(parse_values::<i64>(value).map_err(err)?),
+                "reloc"  => obj.relocs.extend(parse_values::<usize>(value).map_err(err)?),
+                "export" => obj.exports.push(parse_symbol(value).map_err(err)?),
+                "import" => {
+                    let (index, name) = value
+                        .split_once(char::is_whitespace)
+                        .ok_or_else(|| err(String::from("expected <index> <name>")))?;
+                    let index = index.parse().map_err(|_| err(format!("invalid index {}", index)))?;
+                    obj.imports.push((index, name.trim().to_string()));
+                }
+                key => return Err(err(format!("unknown key {}", key))),
+            }
+        }
+        if obj.name.is_empty() {
+            return Err(String::from("missing module name"));
+        }
+        let mut indexes = obj.relocs.iter().chain(obj.imports.iter().map(|(i, _)| i));
+        if let Some(i) = indexes.find(|&&i| i >= obj.code.len()) {
+            return Err(format!("index {} is outside of the code", i));
+        }
+        // All cells of an export have to be part of the module.
+        let inside = |s: &Symbol| s.addr.checked_add(s.kind.cells()).is_some_and(|end| end <= obj.code.len());
+        if let Some(sym) = obj.exports.iter().find(|s| !inside(s)) {
+            return Err(format!("export {} is outside of the module", sym.name));
+        }
+        Ok(obj)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Object, Box<dyn Error>> {
+        Ok(Object::parse(&fs::read_to_string(path)?)?)
+    }
+}
+
+// Writes the object in the text format read by `parse`.
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |vals: Vec<String>| vals.join(",");
+        writeln!(f, "module: {}", self.name)?;
+        writeln!(f, "code: {}", join(self.code.iter().map(|v| v.to_string()).collect()))?;
+        if !self.relocs.is_empty() {
+            writeln!(f, "reloc: {}", join(self.relocs.iter().map(|v| v.to_string()).collect()))?;
+        }
+        for sym in self.exports.iter() {
+            writeln!(f, "export: {}", sym)?;
+        }
+        for (index, name) in self.imports.iter() {
+            writeln!(f, "import: {} {}", index, name)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn link(objects: &[Object]) -> Result<Linked, String> {
+    let mut image   = Vec::new();
+    let mut bases   = Vec::new();
+    let mut symbols = SymbolMap::default();
+    let mut exports = HashMap::new();
+    for obj in objects {
+        let base = image.len();
+        for sym in obj.exports.iter() {
+            if let Some(other) = exports.insert(sym.name.as_str(), (base + sym.addr, &obj.name)) {
+                return Err(format!("{} is exported by {} and {}", sym.name, other.1, obj.name));
+            }
+            symbols.insert(Symbol { addr: base + sym.addr, ..sym.clone() })?;
+        }
+        bases.push(base);
+        image.extend_from_slice(&obj.code);
+    }
+    for (obj, base) in objects.iter().zip(bases) {
+        let mut add = |i: usize, offset: usize| -> Result<(), String> {
+            let word = &mut image[base + i];
+            *word    = i64::try_from(offset)
+                .ok()
+                .and_then(|offset| word.checked_add(offset))
+                .ok_or_else(|| format!("word {} of {} overflows when linked", i, obj.name))?;
+            Ok(())
+        };
+        for &i in obj.relocs.iter() {
+            add(i, base)?;
+        }
+        for (i, name) in obj.imports.iter() {
+            match exports.get(name.as_str()) {
+                Some(&(addr, _)) => add(*i, addr)?,
+                None => return Err(format!("{} imports undefined symbol {}", obj.name, name)),
+            }
+        }
+    }
+    Ok(Linked { image, symbols })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::SymbolType;
+    use crate::IntcodeProg;
+
+    const COUNTER: &str = "\
+        # Returns the number of calls in [rb+1].\n\
+        module: counter\n\
+        code: 1001,11,1,11, 21001,11,0,1, 2105,1,0, 0\n\
+        reloc: 1, 3, 5\n\
+        export: 0 next code\n\
+        export: 11 count word ; number of calls\n";
+
+    // Calls `next` twice and prints the results and `count`.
+    const MAIN: &str = "\
+        module: main\n\
+        code: 109,1000, 21101,0,9,0, 1105,1,0, 204,1\n\
+        code: 21101,0,18,0, 1105,1,0, 204,1, 4,0, 99\n\
+        reloc: 4, 13\n\
+        import: 8 next\n\
+        import: 17 next\n\
+        import: 21 count\n";
+
+    #[test]
+    fn parse_and_link() {
+        let counter = Object::parse(COUNTER).unwrap();
+        let main    = Object::parse(MAIN).unwrap();
+        assert_eq!(counter.exports[0].kind, SymbolType::Code);
+        assert_eq!(counter.exports[1].kind, SymbolType::Word);
+        assert_eq!(main.imports[2], (21, String::from("count")));
+        assert_eq!(Object::parse(&counter.to_string()), Ok(counter.clone()));
+
+        let linked = link(&[main.clone(), counter.clone()]).unwrap();
+        assert_eq!(linked.image.len(), 35);
+        assert_eq!(linked.symbols.name(34), Some(String::from("count")));
+        assert_eq!(linked.symbols.addr("next"), Some(23));
+        assert_eq!(IntcodeProg::exec_prog(&linked.image, vec![]), vec![1, 2, 2]);
+
+        assert_eq!(link(&[main]).unwrap_err(), "main imports undefined symbol next");
+        assert_eq!(
+            link(&[counter.clone(), counter]).unwrap_err(),
+            "next is exported by counter and counter"
+        );
+        // Exports without a type are words, as in symbol files.
+        let obj = Object::parse("module: a\ncode: 99\nexport: 0 start").unwrap();
+        assert_eq!(obj.exports[0].kind, SymbolType::Word);
+        assert!(Object::parse("code: 99").is_err());
+        assert!(Object::parse("module: a\ncode: 99\nreloc: 1").is_err());
+        assert_eq!(
+            Object::parse("module: a\ncode: 99\nexport: 1 end").unwrap_err(),
+            "export end is outside of the module"
+        );
+        assert!(Object::parse("module: a\ncode: 99,0\nexport: 1 table array[2]").is_err());
+
+        let big = Object::parse(&format!("module: big\ncode: 99,{}\nreloc: 1", i64::MAX)).unwrap();
+        let counter = Object::parse(COUNTER).unwrap();
+        assert_eq!(link(&[counter, big]).unwrap_err(), "word 1 of big overflows when linked");
+    }
+}
@@ -0,0 +1,107 @@
+// Pool of Intcode machines that are reset instead of reloaded.
+//
+// Probing a program many times with different inputs (day 19) otherwise
+// copies the whole program for every run. Machines handed out by the pool
+// are returned on drop and reset, which only restores the memory pages
+// that were written to. The pool can be shared between threads.
+
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::sync::Mutex;
+
+use crate::IntcodeProg;
+use crate::Word;
+
+#[derive(Debug)]
+pub struct MachinePool<W: Word = i64> {
+    template: IntcodeProg<W>,
+    idle:     Mutex<Vec<IntcodeProg<W>>>,
+}
+
+// A machine borrowed from the pool, dereferences to `IntcodeProg`.
+#[derive(Debug)]
+pub struct PooledMachine<'a, W: Word = i64> {
+    pool: &'a MachinePool<W>,
+    prog: Option<IntcodeProg<W>>,
+}
+
+impl<W: Word> MachinePool<W> {
+    // The template is reset first, machines are forks of it and thus share
+    // its ISA, overflow policy and extensions.
+    pub fn new(mut template: IntcodeProg<W>) -> MachinePool<W> {
+        template.reset();
+        MachinePool { template, idle: Mutex::new(Vec::new()) }
+    }
+
+    pub fn get(&self) -> PooledMachine<'_, W> {
+        let prog = self.idle.lock().unwrap().pop().unwrap_or_else(|| self.template.fork());
+        PooledMachine { pool: self, prog: Some(prog) }
+    }
+
+    // Number of machines waiting to be handed out again.
+    pub fn idle(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+}
+
+impl<W: Word> Deref for PooledMachine<'_, W> {
+    type Target = IntcodeProg<W>;
+
+    fn deref(&self) -> &IntcodeProg<W> {
+        self.prog.as_ref().unwrap()
+    }
+}
+
+impl<W: Word> DerefMut for PooledMachine<'_, W> {
+    fn deref_mut(&mut self) -> &mut IntcodeProg<W> {
+        self.prog.as_mut().unwrap()
+    }
+}
+
+impl<W: Word> Drop for PooledMachine<'_, W> {
+    fn drop(&mut self) {
+        if let Some(mut prog) = self.prog.take() {
+            prog.reset();
+            // A poisoned lock only means another thread panicked while
+            // returning its machine, the pool itself is still fine.
+            let mut idle = self.pool.idle.lock().unwrap_or_else(|e| e.into_inner());
+            idle.push(prog);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn hand_out_and_reset() {
+        // Outputs the product of its two inputs.
+        let pool = MachinePool::new(IntcodeProg::new(&[3, 11, 3, 12, 2, 11, 12, 11, 4, 11, 99]));
+        {
+            let mut a = pool.get();
+            let mut b = pool.get();
+            assert_eq!(a.run(vec![6, 7]), Ok(vec![42].into()));
+            assert_eq!(b.run(vec![2, 3]), Ok(vec![6].into()));
+        }
+        assert_eq!(pool.idle(), 2);
+        assert_eq!(pool.get().ip(), 0);
+
+        let total: i64 = thread::scope(|s| {
+            let workers: Vec<_> = (0..4)
+                .map(|t| {
+                    let pool = &pool;
+                    s.spawn(move || {
+                        (0..25)
+                            .map(|i| pool.get().run(vec![t, i]).unwrap()[0])
+                            .sum::<i64>()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).sum()
+        });
+        assert_eq!(total, (0..4).sum::<i64>() * (0..25).sum::<i64>());
+        assert!(pool.idle() <= 4);
+    }
+}
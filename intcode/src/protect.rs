@@ -0,0 +1,152 @@
+// Execute-only protection of the code region.
+//
+// The code region consists of the instructions reachable from address 0 as
+// found by `disasm::reachable`, including their parameters. While it is
+// protected, writes to the op code of an instruction and jumps into the
+// middle of an instruction or out of the region are errors.
+//
+// Only op codes are protected. Writes to parameter cells are not detected,
+// since the puzzle programs index their arrays by patching the parameter of
+// the following instruction; `CodeWrites` reports those.
+//
+// Indirect jumps out of the region are only allowed to addresses the code
+// stores as constants, such as callbacks passed as arguments. The first such
+// jump adds the code reachable from there.
+
+use std::collections::BTreeSet;
+
+use crate::disasm;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeRegion {
+    // Cells covered by instructions.
+    code:   Vec<bool>,
+    // Cells where an instruction starts.
+    starts: Vec<bool>,
+    // Constants stored by the code, the legitimate targets of indirect jumps
+    // out of the region.
+    ptrs:   BTreeSet<usize>,
+}
+
+impl CodeRegion {
+    pub fn from_mem(mem: &[i64]) -> CodeRegion {
+        let mut region = CodeRegion {
+            code:   vec![false; mem.len()],
+            starts: vec![false; mem.len()],
+            ptrs:   BTreeSet::new(),
+        };
+        for (&addr, instr) in disasm::reachable(mem).iter() {
+            region.add_instr(addr, instr);
+        }
+        region
+    }
+
+    fn add_instr(&mut self, addr: usize, instr: &disasm::Instr) {
+        self.starts[addr] = true;
+        for cell in addr..instr.next() {
+            self.code[cell] = true;
+        }
+        if let Some((_, val)) = instr.const_write() {
+            if val >= 0 {
+                self.ptrs.insert(val as usize);
+            }
+        }
+    }
+
+    // Adds the code reachable from the target of an indirect jump. Fails if
+    // the target is inside of an instruction, is no constant stored by the
+    // code or holds no valid instruction.
+    pub fn add_entry(&mut self, mem: &[i64], addr: usize) -> bool {
+        if self.contains(addr) || !self.ptrs.contains(&addr) || disasm::decode(mem, addr).is_none() {
+            return false;
+        }
+        if self.code.len() < mem.len() {
+            self.code.resize(mem.len(), false);
+            self.starts.resize(mem.len(), false);
+        }
+        for (&start, instr) in disasm::reachable_from(mem, &[addr]).iter() {
+            if !self.contains(start) {
+                self.add_instr(start, instr);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        self.code.get(addr).copied().unwrap_or(false)
+    }
+
+    // Whether the address holds an op code, which must not be written.
+    pub fn is_instr_start(&self, addr: usize) -> bool {
+        self.starts.get(addr).copied().unwrap_or(false)
+    }
+
+    // Number of protected cells.
+    pub fn cells(&self) -> usize {
+        self.code.iter().filter(|&&c| c).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntcodeProg;
+    use crate::ProgramStatus;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn region() {
+        // jz [9], 8; out 42; jnz 1, 8; halt; data
+        let region = CodeRegion::from_mem(&[1006, 9, 8, 104, 42, 1105, 1, 8, 99, 0]);
+        assert_eq!(region.cells(), 9);
+        assert!(region.contains(4) && region.is_instr_start(3) && !region.is_instr_start(4));
+        assert!(!region.contains(9) && !region.contains(100));
+
+        // Only return addresses stored at [rb+0] are code: add 0, 11, [rb+0];
+        // add 0, 12, [17]; jnz 1, 14; halt; out 1; jnz 1, [rb+0]; data
+        let region = CodeRegion::from_mem(&[
+            21101, 0, 11, 0, 1101, 0, 12, 17, 1105, 1, 14, 99, 104, 1, 2105, 1, 0, 0,
+        ]);
+        assert!(region.is_instr_start(11) && region.is_instr_start(14));
+        assert!(!region.is_instr_start(12) && !region.contains(13));
+    }
+
+    #[test]
+    fn entries() {
+        let mem        = [21101, 0, 11, 0, 1101, 0, 12, 17, 1105, 1, 14, 99, 104, 1, 2105, 1, 0, 0];
+        let mut region = CodeRegion::from_mem(&mem);
+        assert!(!region.add_entry(&mem, 15) && !region.add_entry(&mem, 17));
+        assert!(region.add_entry(&mem, 12));
+        assert!(region.is_instr_start(12) && region.contains(13) && region.is_instr_start(14));
+
+        // The data at 4 decodes to out 5, but no instruction stores 4.
+        let mem        = [106, 0, 7, 99, 104, 5, 99, 4];
+        let mut region = CodeRegion::from_mem(&mem);
+        assert!(!region.add_entry(&mem, 4) && !region.contains(4));
+    }
+
+    #[test]
+    fn puzzle_inputs() {
+        let day = |text: &str| crate::parse_program(text).unwrap();
+        // The text of day 17 and the tables of day 21 are data.
+        let region = CodeRegion::from_mem(&day(include_str!("../../day17/input.txt")));
+        assert!(!region.contains(333));
+        let region = CodeRegion::from_mem(&day(include_str!("../../day21/input.txt")));
+        assert!([3, 5, 65].iter().all(|&addr| !region.is_instr_start(addr)));
+
+        // Day 13 patches parameters, day 25 jumps to callbacks passed as
+        // arguments. Both run protected until they halt or wait for input.
+        for text in [include_str!("../../day13/input.txt"), include_str!("../../day25/input.txt")] {
+            let mut machine = IntcodeProg::new(&day(text));
+            machine.protect_code();
+            let (mut input, mut output) = (VecDeque::new(), VecDeque::new());
+            let status = loop {
+                match machine.try_exec_instr(&mut input, &mut output) {
+                    Ok(ProgramStatus::Success) => (),
+                    status                     => break status,
+                }
+            };
+            assert!(status.is_ok() && !output.is_empty());
+        }
+    }
+}
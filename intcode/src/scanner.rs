@@ -0,0 +1,154 @@
+// Memory scanner for locating variables of a running program.
+//
+// A scan starts with every memory cell as a candidate and a snapshot of the
+// memory. After running the machine for a while, a filter compares each
+// candidate with its value in the snapshot, drops the ones that do not
+// match and takes a new snapshot. A few rounds of "score went up" or "lives
+// stayed the same" usually leave just the cell holding the variable, which
+// can then be edited with `poke` or frozen to a fixed value.
+
+use std::collections::BTreeMap;
+
+use crate::IntcodeError;
+use crate::IntcodeProg;
+use crate::Word;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter<W: Word> {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    Equals(W),
+    // Equal to any of the values, such as the ones seen on output.
+    OneOf(Vec<W>),
+}
+
+impl<W: Word> Filter<W> {
+    fn matches(&self, old: W, new: W) -> bool {
+        match self {
+            Filter::Changed     => new != old,
+            Filter::Unchanged   => new == old,
+            Filter::Increased   => new > old,
+            Filter::Decreased   => new < old,
+            Filter::Equals(val) => new == *val,
+            Filter::OneOf(vals) => vals.contains(&new),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Scanner<W: Word = i64> {
+    // Memory at the last scan.
+    snapshot:   Vec<W>,
+    candidates: Vec<usize>,
+    frozen:     BTreeMap<usize, W>,
+}
+
+impl<W: Word> Default for Scanner<W> {
+    fn default() -> Scanner<W> {
+        Scanner { snapshot: Vec::new(), candidates: Vec::new(), frozen: BTreeMap::new() }
+    }
+}
+
+impl<W: Word> Scanner<W> {
+    // Starts a scan of the whole memory of the machine.
+    pub fn new(prog: &IntcodeProg<W>) -> Scanner<W> {
+        let mut scanner = Scanner::default();
+        scanner.start(prog);
+        scanner
+    }
+
+    // Starts over with every cell as a candidate. Cells the memory grows by
+    // later on are not scanned. Frozen cells are kept.
+    pub fn start(&mut self, prog: &IntcodeProg<W>) {
+        self.snapshot   = prog.mem().to_vec();
+        self.candidates = (0..self.snapshot.len()).collect();
+    }
+
+    // Remaining candidate addresses, in ascending order.
+    pub fn candidates(&self) -> &[usize] {
+        &self.candidates
+    }
+
+    // Keeps the candidates whose current value matches the filter and takes
+    // a new snapshot. Returns the number of remaining candidates.
+    pub fn filter(&mut self, prog: &IntcodeProg<W>, filter: &Filter<W>) -> usize {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|&addr| {
+            let new = prog.mem().get(addr).unwrap_or(W::ZERO);
+            filter.matches(snapshot[addr], new)
+        });
+        self.snapshot = prog.dump_range(0..self.snapshot.len());
+        self.candidates.len()
+    }
+
+    // Keeps the cell at `val` from now on, see `apply`.
+    pub fn freeze(&mut self, addr: usize, val: W) {
+        self.frozen.insert(addr, val);
+    }
+
+    // Releases a frozen cell, returns whether it was frozen.
+    pub fn thaw(&mut self, addr: usize) -> bool {
+        self.frozen.remove(&addr).is_some()
+    }
+
+    pub fn frozen(&self) -> &BTreeMap<usize, W> {
+        &self.frozen
+    }
+
+    // Writes the values of all frozen cells. Called after every step, this
+    // undoes any change the program makes to them.
+    pub fn apply(&self, prog: &mut IntcodeProg<W>) -> Result<(), IntcodeError> {
+        for (&addr, &val) in self.frozen.iter() {
+            if prog.mem().get(addr) != Some(val) {
+                prog.poke(addr as i64, val)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    fn run(prog: &mut IntcodeProg, scanner: &Scanner, steps: usize, output: &mut VecDeque<i64>) {
+        for _ in 0..steps {
+            prog.try_exec_instr(&mut VecDeque::new(), output).unwrap();
+            scanner.apply(prog).unwrap();
+        }
+    }
+
+    #[test]
+    fn find_and_freeze() {
+        // Counts down [20] and up [21], outputs [21] until [20] is zero.
+        let mut mem = vec![101, -1, 20, 20, 1001, 21, 1, 21, 4, 21, 1005, 20, 0, 99];
+        mem.resize(20, 0);
+        mem.extend_from_slice(&[3, 0]);
+        let mut prog    = IntcodeProg::new(&mem);
+        let mut scanner = Scanner::new(&prog);
+        let mut output  = VecDeque::new();
+
+        run(&mut prog, &scanner, 4, &mut output);
+        assert_eq!(scanner.filter(&prog, &Filter::Changed), 2);
+        assert_eq!(scanner.filter(&prog, &Filter::OneOf(output.iter().copied().collect())), 1);
+        assert_eq!(scanner.candidates(), &[21]);
+
+        scanner.start(&prog);
+        run(&mut prog, &scanner, 4, &mut output);
+        scanner.filter(&prog, &Filter::Decreased);
+        assert_eq!(scanner.candidates(), &[20]);
+        assert_eq!(scanner.filter(&prog, &Filter::Equals(1)), 1);
+
+        // Frozen, the countdown never ends.
+        scanner.freeze(20, 2);
+        run(&mut prog, &scanner, 40, &mut output);
+        assert_eq!(prog.peek(20).unwrap(), 2);
+        assert_eq!(prog.peek(21).unwrap(), 12);
+        assert!(scanner.thaw(20) && !scanner.thaw(20));
+        run(&mut prog, &scanner, 8, &mut output);
+        assert_eq!(prog.mem()[prog.ip()], 99);
+    }
+}
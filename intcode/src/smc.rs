@@ -0,0 +1,114 @@
+// Detection of self-modifying code.
+//
+// When enabled, the machine remembers which cells were executed as part of
+// an instruction (op code and parameters) and which were written. A write to
+// a cell that was already executed is a write into code, and so is a write
+// to a cell that is executed later on (a forward patch), which is detected
+// when the instruction is fetched. Day 2 style programs patch their own
+// parameters this way.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::word::Word;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeWrites {
+    // Nothing is tracked.
+    #[default]
+    Ignore,
+    // Writes into code are recorded.
+    Record,
+    // Writes into code are reported as an error.
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeWrite<W: Word> {
+    // Position of the writing instruction.
+    pub ip:   usize,
+    pub addr: usize,
+    pub old:  W,
+    pub new:  W,
+}
+
+impl<W: Word> fmt::Display for CodeWrite<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ip {} wrote {} to {} (was {})", self.ip, self.new, self.addr, self.old)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SmcTracker<W: Word> {
+    executed: Vec<bool>,
+    written:  Vec<bool>,
+    // Last write to each cell that has not been executed yet.
+    pending:  BTreeMap<usize, CodeWrite<W>>,
+    writes:   Vec<CodeWrite<W>>,
+}
+
+impl<W: Word> Default for SmcTracker<W> {
+    fn default() -> SmcTracker<W> {
+        SmcTracker { executed: Vec::new(), written: Vec::new(), pending: BTreeMap::new(), writes: Vec::new() }
+    }
+}
+
+fn mark(cells: &mut Vec<bool>, addr: usize) {
+    if cells.len() <= addr {
+        cells.resize(addr + 1, false);
+    }
+    cells[addr] = true;
+}
+
+impl<W: Word> SmcTracker<W> {
+    pub fn clear(&mut self) {
+        self.executed.clear();
+        self.written.clear();
+        self.pending.clear();
+        self.writes.clear();
+    }
+
+    // First write to the cells of the instruction at ip that happened
+    // before they were executed.
+    pub fn patched(&self, ip: usize, len: usize) -> Option<&CodeWrite<W>> {
+        self.pending.range(ip..ip + len).next().map(|(_, write)| write)
+    }
+
+    // Marks the cells of the instruction at ip as executed and records the
+    // writes that patched them in advance.
+    pub fn mark_executed(&mut self, ip: usize, len: usize) {
+        for addr in ip..ip + len {
+            mark(&mut self.executed, addr);
+            if let Some(write) = self.pending.remove(&addr) {
+                self.writes.push(write);
+            }
+        }
+    }
+
+    pub fn is_executed(&self, addr: usize) -> bool {
+        self.executed.get(addr).copied().unwrap_or(false)
+    }
+
+    pub fn mark_written(&mut self, write: CodeWrite<W>) {
+        mark(&mut self.written, write.addr);
+        if self.is_executed(write.addr) {
+            self.writes.push(write);
+        } else {
+            self.pending.insert(write.addr, write);
+        }
+    }
+
+    // Writes into code in the order they were detected: writes into cells
+    // that had been executed before when they happen, forward patches when
+    // the patched instruction is executed.
+    pub fn writes(&self) -> &[CodeWrite<W>] {
+        &self.writes
+    }
+
+    // Cells that were both executed and written, in either order.
+    pub fn modified(&self) -> Vec<usize> {
+        (0..self.executed.len().min(self.written.len()))
+            .filter(|&addr| self.executed[addr] && self.written[addr])
+            .collect()
+    }
+}
@@ -0,0 +1,134 @@
+// Streaming output with a handler.
+//
+// `run_with` hands the output of a machine to a handler in fixed-size tuples
+// as soon as the last value of a tuple is output: day 11 outputs pairs of
+// color and turn, day 13 triples of x, y and tile. The handler answers by
+// pushing input, either right away or once the machine asks for it, and may
+// stop the run. A stopped machine keeps its state and can be run again;
+// outputs not handed to the handler yet are dropped.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+
+use crate::IntcodeError;
+use crate::IntcodeProg;
+use crate::ProgramStatus;
+use crate::Word;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Stop,
+}
+
+pub trait OutputHandler<W, const N: usize> {
+    fn output(&mut self, tuple: [W; N], input: &mut VecDeque<W>) -> Flow;
+
+    // Called when the machine waits for input and none is queued. If no
+    // input is pushed, the run fails with `MissingInput`.
+    fn input(&mut self, _input: &mut VecDeque<W>) -> Flow {
+        Flow::Continue
+    }
+}
+
+// Closures handle the output, e.g.
+// `|[color, turn]: [i64; 2], input: &mut VecDeque<i64>| { ... }`.
+impl<W, F, const N: usize> OutputHandler<W, N> for F
+where
+    F: FnMut([W; N], &mut VecDeque<W>) -> Flow,
+{
+    fn output(&mut self, tuple: [W; N], input: &mut VecDeque<W>) -> Flow {
+        self(tuple, input)
+    }
+}
+
+impl<W: Word> IntcodeProg<W> {
+    // Runs with the given initial input until the program halts or the
+    // handler stops it. Halting in the middle of a tuple is an error.
+    pub fn run_with<H, const N: usize>(&mut self, input: Vec<W>, handler: &mut H) -> Result<(), IntcodeError>
+    where
+        H: OutputHandler<W, N>,
+    {
+        assert!(N > 0, "output tuples must not be empty");
+        let mut inputs  = VecDeque::from(input);
+        let mut outputs = VecDeque::new();
+        loop {
+            let status = self.try_exec_instr(&mut inputs, &mut outputs)?;
+            while outputs.len() >= N {
+                let tuple: Vec<_> = outputs.drain(..N).collect();
+                if handler.output(tuple.try_into().unwrap(), &mut inputs) == Flow::Stop {
+                    return Ok(());
+                }
+            }
+            match status {
+                ProgramStatus::Success                        => (),
+                ProgramStatus::Finished if outputs.is_empty() => break Ok(()),
+                ProgramStatus::Finished                       => {
+                    break Err(IntcodeError::IncompleteOutput { ip: self.ip, count: outputs.len() })
+                }
+                ProgramStatus::WaitingForInput                => {
+                    if handler.input(&mut inputs) == Flow::Stop {
+                        break Ok(());
+                    }
+                    if inputs.is_empty() {
+                        break Err(IntcodeError::MissingInput { ip: self.ip });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs the input and its double until the input is zero.
+    const DOUBLER: [i64; 15] = [3, 14, 4, 14, 1002, 14, 2, 14, 4, 14, 1005, 14, 0, 99, 0];
+
+    #[test]
+    fn pairs() {
+        let mut machine = IntcodeProg::new(&DOUBLER);
+        let mut pairs   = Vec::new();
+        let mut handler = |[val, double]: [i64; 2], input: &mut VecDeque<i64>| {
+            pairs.push((val, double));
+            input.push_back(val - 1);
+            Flow::Continue
+        };
+        assert_eq!(machine.run_with(vec![3], &mut handler), Ok(()));
+        assert_eq!(pairs, vec![(3, 6), (2, 4), (1, 2), (0, 0)]);
+
+        // Halting after the first value of a triple.
+        let mut machine = IntcodeProg::new(&DOUBLER);
+        let mut handler = |_: [i64; 3], _: &mut VecDeque<i64>| Flow::Continue;
+        let err = machine.run_with(vec![1, 0], &mut handler).unwrap_err();
+        assert_eq!(err, IntcodeError::IncompleteOutput { ip: 13, count: 1 });
+    }
+
+    struct Countdown {
+        seen: Vec<i64>,
+    }
+
+    impl OutputHandler<i64, 1> for Countdown {
+        fn output(&mut self, [val]: [i64; 1], _: &mut VecDeque<i64>) -> Flow {
+            self.seen.push(val);
+            if val == 6 { Flow::Stop } else { Flow::Continue }
+        }
+
+        fn input(&mut self, input: &mut VecDeque<i64>) -> Flow {
+            input.push_back(5 - self.seen.len() as i64);
+            Flow::Continue
+        }
+    }
+
+    #[test]
+    fn input_on_demand() {
+        let mut machine = IntcodeProg::new(&DOUBLER);
+        let mut handler = Countdown { seen: Vec::new() };
+        assert_eq!(machine.run_with(Vec::new(), &mut handler), Ok(()));
+        assert_eq!(handler.seen, vec![5, 10, 3, 6]);
+        assert_eq!(machine.ip(), 10);
+        assert_eq!(machine.run_with(vec![0], &mut handler), Ok(()));
+        assert_eq!(handler.seen, vec![5, 10, 3, 6, 0, 0]);
+    }
+}
@@ -0,0 +1,294 @@
+// Extraction of strings and data tables from program images.
+//
+// Strings are found outside of the code region (see the protect module) in
+// three layouts:
+//
+//     prefixed    a length cell followed by that many characters
+//     terminated  characters followed by a zero cell
+//     run         any other run of at least twice the minimum length
+//
+// Characters are printable ASCII or newlines, at least half of them have to
+// be letters and three quarters have to be typical for English text (see
+// `score`). Prefixed strings may also be stored shifted, with
+// every character reduced by the string length and its index (day 25 does
+// this). Tables are runs of data cells with only a few distinct values,
+// such as the screen of day 13.
+
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::protect::CodeRegion;
+use crate::symbols::Symbol;
+use crate::symbols::SymbolType;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Prefixed,
+    Terminated,
+    Run,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Ascii,
+    // Character minus string length minus index.
+    Shifted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    // First cell, the length cell of prefixed strings.
+    pub addr:     usize,
+    // Number of cells including length cell and terminator.
+    pub cells:    usize,
+    pub layout:   Layout,
+    pub encoding: Encoding,
+    pub text:     String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub addr:   usize,
+    pub cells:  usize,
+    // Distinct values, sorted.
+    pub values: Vec<i64>,
+}
+
+// Most distinct values a table may contain.
+const TABLE_VALUES: usize = 16;
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let layout = match self.layout {
+            Layout::Prefixed   => "prefixed",
+            Layout::Terminated => "terminated",
+            Layout::Run        => "run",
+        };
+        let encoding = match self.encoding {
+            Encoding::Ascii   => "ascii",
+            Encoding::Shifted => "shifted",
+        };
+        write!(f, "{:>6}  {:<10} {:<7} {:?}", self.addr, layout, encoding, self.text)
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<_> = self.values.iter().map(|v| v.to_string()).collect();
+        write!(f, "{:>6}  table      {} cells, values {}", self.addr, self.cells, values.join(" "))
+    }
+}
+
+impl Text {
+    // Symbol for the string, named after its address. Plain ASCII strings
+    // become `string` symbols that disassembly listings print as text.
+    pub fn symbol(&self) -> Symbol {
+        let (addr, cells) = match self.layout {
+            Layout::Prefixed   => (self.addr + 1, self.cells - 1),
+            Layout::Terminated => (self.addr, self.cells - 1),
+            Layout::Run        => (self.addr, self.cells),
+        };
+        let (addr, kind) = match self.encoding {
+            Encoding::Ascii   => (addr, SymbolType::Text(cells)),
+            Encoding::Shifted => (self.addr, SymbolType::Array(self.cells)),
+        };
+        let name    = format!("str_{}", addr);
+        let comment = Some(format!("{:?}", self.text));
+        Symbol { addr, name, kind, comment }
+    }
+}
+
+fn is_char(val: i64) -> bool {
+    (32..127).contains(&val) || val == 10
+}
+
+fn letters(text: &str) -> usize {
+    text.chars().filter(|c| c.is_ascii_alphabetic()).count()
+}
+
+// Number of characters typical for English text: lower case letters, upper
+// case letters after white space, white space and common punctuation.
+fn score(text: &str) -> usize {
+    let mut prev  = ' ';
+    let mut score = 0;
+    for c in text.chars() {
+        let upper = c.is_ascii_uppercase() && prev.is_ascii_whitespace();
+        if upper || c.is_ascii_lowercase() || " \n.,:;!?'-".contains(c) {
+            score += 1;
+        }
+        prev = c;
+    }
+    score
+}
+
+// Random data easily looks like text, so strings have to read mostly like
+// natural text.
+fn natural(text: &str) -> bool {
+    4 * score(text) >= 3 * text.len()
+}
+
+fn text_like(chars: &[i64]) -> Option<String> {
+    if !chars.iter().all(|&c| is_char(c)) {
+        return None;
+    }
+    let text: String = chars.iter().map(|&c| c as u8 as char).collect();
+    if 2 * letters(&text) >= text.len() {
+        Some(text)
+    } else {
+        None
+    }
+}
+
+fn prefixed(mem: &[i64], addr: usize, min_len: usize, code: &CodeRegion) -> Option<Text> {
+    let len = usize::try_from(mem[addr]).ok().filter(|&n| n >= min_len.max(1))?;
+    let end = addr + 1 + len;
+    if end > mem.len() || (addr..end).any(|a| code.contains(a)) {
+        return None;
+    }
+    let body = &mem[addr + 1..end];
+    // Cells that overflow when shifted rule out the shifted encoding.
+    let shifted: Option<Vec<_>> =
+        body.iter().enumerate().map(|(i, &c)| c.checked_add((len + i) as i64)).collect();
+    // Short strings can look like text either way, take the more natural
+    // one.
+    let natural          = |text: &String| natural(text);
+    let shifted          = shifted.and_then(|cells| text_like(&cells)).filter(natural);
+    let (encoding, text) = match (text_like(body).filter(natural), shifted) {
+        (Some(a), Some(s)) if score(&s) > score(&a) => (Encoding::Shifted, s),
+        (Some(text), _)                               => (Encoding::Ascii, text),
+        (_, Some(text))                               => (Encoding::Shifted, text),
+        _                                             => return None,
+    };
+    Some(Text { addr, cells: len + 1, layout: Layout::Prefixed, encoding, text })
+}
+
+// All strings with at least `min_len` characters, by address.
+pub fn strings(mem: &[i64], min_len: usize) -> Vec<Text> {
+    let code      = CodeRegion::from_mem(mem);
+    let mut found = Vec::new();
+    let mut addr  = 0;
+    while addr < mem.len() {
+        match prefixed(mem, addr, min_len, &code) {
+            Some(text) => {
+                addr += text.cells;
+                found.push(text);
+            }
+            None => addr += 1,
+        }
+    }
+
+    let mut taken = vec![false; mem.len()];
+    for text in found.iter() {
+        taken[text.addr..text.addr + text.cells].iter_mut().for_each(|t| *t = true);
+    }
+    let mut addr = 0;
+    while addr < mem.len() {
+        let start = addr;
+        while addr < mem.len() && !taken[addr] && !code.contains(addr) && is_char(mem[addr]) {
+            addr += 1;
+        }
+        if addr == start {
+            addr += 1;
+            continue;
+        }
+        let text = text_like(&mem[start..addr]).filter(|t| t.len() >= min_len && natural(t));
+        if let Some(text) = text {
+            // Without a terminator, only longer runs are taken for text.
+            let terminated      = mem.get(addr) == Some(&0) && !taken[addr] && !code.contains(addr);
+            let (layout, cells) = if terminated {
+                (Layout::Terminated, addr - start + 1)
+            } else if text.len() >= 2 * min_len {
+                (Layout::Run, addr - start)
+            } else {
+                continue;
+            };
+            found.push(Text { addr: start, cells, layout, encoding: Encoding::Ascii, text });
+        }
+    }
+    found.sort_by_key(|text| text.addr);
+    found
+}
+
+// Tables of at least `min_len` cells outside of code and strings.
+pub fn tables(mem: &[i64], min_len: usize, strings: &[Text]) -> Vec<Table> {
+    let code      = CodeRegion::from_mem(mem);
+    let mut data  = vec![true; mem.len()];
+    for addr in (0..mem.len()).filter(|&a| code.contains(a)) {
+        data[addr] = false;
+    }
+    for text in strings {
+        data[text.addr..text.addr + text.cells].iter_mut().for_each(|d| *d = false);
+    }
+    let mut found = Vec::new();
+    let mut addr  = 0;
+    while addr < mem.len() {
+        let mut values = BTreeSet::new();
+        let mut end    = addr;
+        while end < mem.len() && data[end] {
+            values.insert(mem[end]);
+            if values.len() > TABLE_VALUES {
+                values.remove(&mem[end]);
+                break;
+            }
+            end += 1;
+        }
+        if end - addr >= min_len.max(1) {
+            found.push(Table { addr, cells: end - addr, values: values.into_iter().collect() });
+            addr = end;
+        } else {
+            addr += 1;
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<i64> {
+        text.bytes().map(|b| b as i64).collect()
+    }
+
+    #[test]
+    fn extraction() {
+        // out [0]; halt; then the data.
+        let mut mem = vec![4, 0, 99, 5];
+        mem.extend(chars("Main:"));
+        mem.extend(chars("Walking...\n"));
+        mem.push(0);
+        mem.push(5);
+        mem.extend(chars("north").iter().enumerate().map(|(i, c)| c - 5 - i as i64));
+        mem.extend(vec![0, 1, 2, 1, 0, 0, 1, 2, 2, 2]);
+        mem.extend(chars("12345"));
+
+        let found = strings(&mem, 4);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].to_string(), "     3  prefixed   ascii   \"Main:\"");
+        assert_eq!((found[1].layout, found[1].cells), (Layout::Terminated, 12));
+        assert_eq!((found[2].encoding, found[2].text.as_str()), (Encoding::Shifted, "north"));
+        assert_eq!(found[0].symbol().kind, SymbolType::Text(5));
+        assert_eq!(found[0].symbol().addr, 4);
+
+        let tables = tables(&mem, 8, &found);
+        assert_eq!(tables.len(), 1);
+        assert_eq!((tables[0].addr, tables[0].cells), (27, 15));
+        assert_eq!(tables[0].values.len(), 3 + 5);
+
+        // Shifting a huge cell overflows, which is no string either way.
+        assert!(strings(&[99, 5, 104, 101, 108, i64::MAX, 111], 4).is_empty());
+    }
+
+    #[test]
+    fn puzzle_inputs() {
+        let day   = |text: &str| crate::parse_program(text).unwrap();
+        let found = strings(&day(include_str!("../../day17/input.txt")), 4);
+        assert_eq!(found.len(), 9);
+        assert_eq!(found[0].to_string(), "   333  prefixed   ascii   \"Main:\\n\"");
+        assert!(found.iter().all(|text| text.layout == Layout::Prefixed));
+
+        // The data of day 13 holds no text at all.
+        assert!(strings(&day(include_str!("../../day13/input.txt")), 4).is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+// Symbol maps for annotated Intcode programs.
+//
+// A symbol file lives next to the program (`input.txt` -> `input.sym`) and
+// has one symbol per line:
+//
+//     # day 13
+//     0      main          code
+//     386    score         word       ; current score
+//     392    paddle_x
+//     639    tiles         array[880] ; screen contents
+//     1000   prompt        string[12]
+//
+// The type is one of `code`, `word` (the default), `array[n]` or `string[n]`
+// where `n` is the number of cells. Everything after a `;` is a comment.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolType {
+    Code,
+    Word,
+    Array(usize),
+    Text(usize),
+}
+
+impl SymbolType {
+    // Number of memory cells covered by the symbol.
+    pub fn cells(self) -> usize {
+        match self {
+            SymbolType::Array(n) | SymbolType::Text(n) => n,
+            _                                           => 1,
+        }
+    }
+}
+
+impl fmt::Display for SymbolType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolType::Code     => write!(f, "code"),
+            SymbolType::Word     => write!(f, "word"),
+            SymbolType::Array(n) => write!(f, "array[{}]", n),
+            SymbolType::Text(n)  => write!(f, "string[{}]", n),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub addr:    usize,
+    pub name:    String,
+    pub kind:    SymbolType,
+    pub comment: Option<String>,
+}
+
+// Writes the symbol as a line of a symbol file.
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.addr, self.name, self.kind)?;
+        match &self.comment {
+            Some(comment) => write!(f, " ; {}", comment),
+            None          => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    symbols: BTreeMap<usize, Symbol>,
+}
+
+fn parse_type(text: &str) -> Option<SymbolType> {
+    let sized = |prefix: &str| -> Option<usize> {
+        text.strip_prefix(prefix)?.strip_suffix(']')?.parse().ok().filter(|&n| n > 0)
+    };
+    match text {
+        "code" => Some(SymbolType::Code),
+        "word" => Some(SymbolType::Word),
+        _      => sized("array[")
+            .map(SymbolType::Array)
+            .or_else(|| sized("string[").map(SymbolType::Text)),
+    }
+}
+
+// Parses `<addr> <name> [type] [; comment]`, also used for the exports of
+// object files.
+pub(crate) fn parse_symbol(text: &str) -> Result<Symbol, String> {
+    let (text, comment) = match text.split_once(';') {
+        Some((text, comment)) => (text, Some(comment.trim().to_string())),
+        None                  => (text, None),
+    };
+    let words: Vec<_> = text.split_whitespace().collect();
+    if words.len() < 2 || words.len() > 3 {
+        return Err(String::from("expected <addr> <name> [type]"));
+    }
+    let addr = words[0].parse().map_err(|_| format!("invalid address {}", words[0]))?;
+    let kind = match words.get(2) {
+        Some(t) => parse_type(t).ok_or_else(|| format!("invalid type {}", t))?,
+        None    => SymbolType::Word,
+    };
+    Ok(Symbol { addr, name: words[1].to_string(), kind, comment })
+}
+
+impl SymbolMap {
+    pub fn parse(text: &str) -> Result<SymbolMap, String> {
+        let mut map = SymbolMap::default();
+        for (i, line) in text.lines().enumerate() {
+            let err  = |msg: String| format!("line {}: {}", i + 1, msg);
+            let head = line.split(';').next().unwrap_or_default().trim();
+            if head.is_empty() || head.starts_with('#') {
+                continue;
+            }
+            let symbol = parse_symbol(line).map_err(err)?;
+            if symbol.name.parse::<i64>().is_ok() || map.addr(&symbol.name).is_some() {
+                return Err(err(format!("invalid or duplicate name {}", symbol.name)));
+            }
+            map.insert(symbol).map_err(err)?;
+        }
+        Ok(map)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SymbolMap, Box<dyn Error>> {
+        Ok(SymbolMap::parse(&fs::read_to_string(path)?)?)
+    }
+
+    // Path of the symbol file belonging to a program file.
+    pub fn sidecar_path<P: AsRef<Path>>(prog: P) -> PathBuf {
+        prog.as_ref().with_extension("sym")
+    }
+
+    // Loads the symbol file next to the program, or returns an empty map if
+    // there is none.
+    pub fn load_sidecar<P: AsRef<Path>>(prog: P) -> Result<SymbolMap, Box<dyn Error>> {
+        let path = SymbolMap::sidecar_path(prog);
+        if path.exists() {
+            SymbolMap::load(path)
+        } else {
+            Ok(SymbolMap::default())
+        }
+    }
+
+    // Adds a symbol, at most one may start at every address.
+    pub fn insert(&mut self, symbol: Symbol) -> Result<(), String> {
+        if let Some(other) = self.symbols.get(&symbol.addr) {
+            return Err(format!("{} and {} both start at address {}", other.name, symbol.name, symbol.addr));
+        }
+        self.symbols.insert(symbol.addr, symbol);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.values()
+    }
+
+    // Symbol starting exactly at the address.
+    pub fn get(&self, addr: usize) -> Option<&Symbol> {
+        self.symbols.get(&addr)
+    }
+
+    // Symbol covering the address, with the offset into it.
+    pub fn lookup(&self, addr: usize) -> Option<(&Symbol, usize)> {
+        let (_, sym) = self.symbols.range(..=addr).next_back()?;
+        let offset   = addr - sym.addr;
+        if offset < sym.kind.cells() {
+            Some((sym, offset))
+        } else {
+            None
+        }
+    }
+
+    // Name for an address, e.g. `score` or `tiles+12`.
+    pub fn name(&self, addr: usize) -> Option<String> {
+        match self.lookup(addr)? {
+            (sym, 0)      => Some(sym.name.clone()),
+            (sym, offset) => Some(format!("{}+{}", sym.name, offset)),
+        }
+    }
+
+    // Resolves a name (optionally with an offset) or a plain number.
+    pub fn addr(&self, text: &str) -> Option<usize> {
+        if let Ok(addr) = text.parse() {
+            return Some(addr);
+        }
+        let (name, offset) = match text.split_once('+') {
+            Some((name, offset)) => (name, offset.parse().ok()?),
+            None                 => (text, 0),
+        };
+        self.symbols.values().find(|s| s.name == name)?.addr.checked_add(offset)
+    }
+}
+
+// Writes the map in the format read by `parse`.
+impl fmt::Display for SymbolMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for sym in self.symbols.values() {
+            writeln!(f, "{}", sym)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_lookup() {
+        let text = "\
+            # day 13\n\
+            0    main   code\n\
+            386  score  ; current score\n\
+            639  tiles  array[880]\n\
+            1000 prompt string[12] ; shown at the start\n";
+        let map = SymbolMap::parse(text).unwrap();
+        assert_eq!(map.get(386).unwrap().comment.as_deref(), Some("current score"));
+        assert_eq!(map.get(386).unwrap().kind, SymbolType::Word);
+        assert_eq!(map.get(1000).unwrap().kind, SymbolType::Text(12));
+        assert_eq!(map.name(386), Some(String::from("score")));
+        assert_eq!(map.name(387), None);
+        assert_eq!(map.name(651), Some(String::from("tiles+12")));
+        assert_eq!(map.addr("tiles+12"), Some(651));
+        assert_eq!(map.addr("42"), Some(42));
+        assert_eq!(map.addr("paddle"), None);
+        assert_eq!(SymbolMap::parse(&map.to_string()), Ok(map.clone()));
+
+        assert!(SymbolMap::parse("12").is_err());
+        assert!(SymbolMap::parse("1 a array[0]").is_err());
+        assert!(SymbolMap::parse("1 a\n2 a").is_err());
+        assert_eq!(SymbolMap::parse("1 a\n1 b").unwrap_err(), "line 2: a and b both start at address 1");
+        assert_eq!(map.addr("tiles+18446744073709551615"), None);
+        assert_eq!(SymbolMap::sidecar_path("day13/input.txt"), PathBuf::from("day13/input.sym"));
+    }
+}
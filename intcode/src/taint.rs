@@ -0,0 +1,279 @@
+// Dynamic taint tracking from inputs to outputs.
+//
+// Every input value consumed gets a label, its index among all inputs. The
+// labels travel with the values: a written cell carries the labels of the
+// operands, of the address it was written to and the control taint; an output
+// carries the labels of its value and the control taint. Reading through a
+// tainted pointer or relative base adds their labels as well.
+//
+// The control taint holds the labels of the conditions and targets of the
+// jumps that decided how execution got to the current instruction. A
+// conditional jump only matters until its branches join again, at the
+// immediate post dominator of its block in the control-flow graph (see the
+// cfg module). Its labels are dropped once that address is reached with the
+// relative base the jump saw, so recursive calls do not drop them early.
+// Labels of jumps without such a join, like jumps to computed addresses,
+// are kept for the rest of the run.
+
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+use crate::cfg::Cfg;
+use crate::cfg::Exit;
+
+// Indices of the inputs a value depends on.
+pub type Labels = BTreeSet<usize>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaintTracker {
+    // Labels of tainted memory cells, cells without labels are left out.
+    cells:    HashMap<usize, Labels>,
+    rel_base: Labels,
+    // Control taint of the jumps without a join.
+    control:  Labels,
+    // Conditional jumps whose branches have not joined yet, innermost last.
+    branches: Vec<Branch>,
+    // Address where the branches of the conditional jump at an address join.
+    joins:    HashMap<usize, usize>,
+    // Number of inputs consumed.
+    inputs:   usize,
+    outputs:  Vec<Labels>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Branch {
+    join:     usize,
+    rel_base: i64,
+    labels:   Labels,
+}
+
+// Position of an instruction parameter with the labels of its address and
+// of its value (including the address labels).
+#[derive(Debug, Clone)]
+pub(crate) struct Param {
+    pub pos:   usize,
+    pub addr:  Labels,
+    pub value: Labels,
+}
+
+// Changes of one instruction, applied once it completed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Update {
+    writes:   Vec<(usize, Labels)>,
+    output:   Labels,
+    control:  Labels,
+    // Join and relative base of a conditional jump.
+    branch:   Option<(usize, i64)>,
+    rel_base: Labels,
+}
+
+fn union<'a>(sets: impl IntoIterator<Item = &'a Labels>) -> Labels {
+    sets.into_iter().flatten().copied().collect()
+}
+
+impl TaintTracker {
+    // Tracker without control-flow graph, which keeps the labels of every
+    // jump for the rest of the run.
+    pub fn new() -> TaintTracker {
+        TaintTracker::default()
+    }
+
+    // Tracker for the program in memory, with the joins of its conditional
+    // jumps.
+    pub fn for_program(mem: &[i64]) -> TaintTracker {
+        let cfg       = Cfg::build(mem);
+        let mut joins = HashMap::new();
+        for func in cfg.functions() {
+            for (b, join) in cfg.post_dominators(&func) {
+                let block = &cfg.blocks[&b];
+                if let (Exit::Branch { .. }, Some(jump)) = (&block.exit, block.jump()) {
+                    joins.insert(jump.addr, join);
+                }
+            }
+        }
+        TaintTracker { joins, ..TaintTracker::default() }
+    }
+
+    // Forgets all labels, the joins are kept.
+    pub fn clear(&mut self) {
+        let joins = std::mem::take(&mut self.joins);
+        *self     = TaintTracker { joins, ..TaintTracker::default() };
+    }
+
+    pub fn cell(&self, addr: usize) -> Labels {
+        self.cells.get(&addr).cloned().unwrap_or_default()
+    }
+
+    // Labels of the jumps the current instruction depends on.
+    pub fn control(&self) -> Labels {
+        union(self.branches.iter().map(|b| &b.labels).chain(Some(&self.control)))
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    // Labels of every value output so far, in order.
+    pub fn outputs(&self) -> &[Labels] {
+        &self.outputs
+    }
+
+    // Inputs that influenced no output at all.
+    pub fn unused(&self) -> Labels {
+        let used = union(self.outputs.iter());
+        (0..self.inputs).filter(|i| !used.contains(i)).collect()
+    }
+
+    // Parameter in the given mode stored at `cell` and referring to `pos`.
+    pub(crate) fn param(&self, mode: i64, cell: usize, pos: usize) -> Param {
+        let addr = match mode {
+            0 => self.cell(cell),
+            1 => Labels::new(),
+            _ => union(vec![&self.cell(cell), &self.rel_base]),
+        };
+        let value = union(vec![&addr, &self.cell(pos)]);
+        Param { pos, addr, value }
+    }
+
+    // Drops the labels of the branches that join at the instruction about to
+    // be executed, along with the ones nested in them.
+    pub(crate) fn reach(&mut self, ip: usize, rel_base: i64) {
+        if let Some(i) = self.branches.iter().rposition(|b| b.join == ip && b.rel_base == rel_base) {
+            self.branches.truncate(i);
+        }
+    }
+
+    // Effect of the instruction at `ip` with the given op code and
+    // parameters. For extensions, `writes` are the indices of the
+    // parameters they write.
+    pub(crate) fn update(
+        &self,
+        ip:       usize,
+        rel_base: i64,
+        op_code:  i64,
+        params:   &[Param],
+        writes:   &[usize],
+    ) -> Update {
+        let mut update = Update::default();
+        let control    = self.control();
+        let values     = union(params.iter().map(|p| &p.value).chain(Some(&control)));
+        match op_code {
+            1 | 2 | 7 | 8 => {
+                let labels = union(vec![&params[0].value, &params[1].value, &params[2].addr, &control]);
+                update.writes.push((params[2].pos, labels));
+            }
+            3 => update.writes.push((params[0].pos, union(vec![&params[0].addr, &control]))),
+            4 => update.output = values,
+            5 | 6 => {
+                // The enclosing branches are tracked already.
+                update.control = union(params.iter().map(|p| &p.value));
+                update.branch  = self.joins.get(&ip).map(|&join| (join, rel_base));
+            }
+            9 => update.rel_base = union(vec![&params[0].value, &control]),
+            99 => (),
+            _ => {
+                for &w in writes {
+                    update.writes.push((params[w].pos, union(vec![&values, &params[w].addr])));
+                }
+                update.output = values;
+            }
+        }
+        update
+    }
+
+    // Applies an update for an instruction that consumed `consumed` inputs
+    // and output `produced` values. The new input labels go to every cell
+    // written and value output.
+    pub(crate) fn apply(&mut self, update: Update, consumed: usize, produced: usize) {
+        let new: Labels = (self.inputs..self.inputs + consumed).collect();
+        self.inputs += consumed;
+        for (pos, labels) in update.writes {
+            let labels = union(vec![&labels, &new]);
+            if labels.is_empty() {
+                self.cells.remove(&pos);
+            } else {
+                self.cells.insert(pos, labels);
+            }
+        }
+        let output = union(vec![&update.output, &new]);
+        for _ in 0..produced {
+            self.outputs.push(output.clone());
+        }
+        match update.branch {
+            _ if update.control.is_empty() => (),
+            Some((join, rel_base))         => {
+                // Loops jump to the same join in every iteration.
+                let same = self.branches.iter_mut().find(|b| b.join == join && b.rel_base == rel_base);
+                match same {
+                    Some(branch) => branch.labels.extend(update.control),
+                    None         => self.branches.push(Branch { join, rel_base, labels: update.control }),
+                }
+            }
+            None => self.control.extend(update.control),
+        }
+        self.rel_base.extend(update.rel_base);
+    }
+}
+
+// Labels as comma-separated ranges, e.g. `0-3,7`.
+pub fn ranges(labels: &Labels) -> String {
+    let mut parts: Vec<(usize, usize)> = Vec::new();
+    for &label in labels {
+        match parts.last_mut() {
+            Some((_, end)) if *end + 1 == label => *end = label,
+            _                                   => parts.push((label, label)),
+        }
+    }
+    let parts: Vec<_> = parts
+        .into_iter()
+        .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+        .collect();
+    parts.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IntcodeProg;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn label_ranges() {
+        let labels: Labels = vec![0, 1, 2, 3, 7, 9, 10].into_iter().collect();
+        assert_eq!(ranges(&labels), "0-3,7,9-10");
+        assert_eq!(ranges(&Labels::new()), "");
+    }
+
+    #[test]
+    fn scoped_control() {
+        let prog = crate::compiler::compile(
+            "fn main() {
+                 var n = input();
+                 var ignored = input();
+                 var i = 0;
+                 while (i < n) { output(i); i = i + 1; }
+                 output(input());
+             }",
+        )
+        .unwrap();
+        let mut machine = IntcodeProg::new(&prog);
+        machine.enable_taint();
+        assert_eq!(machine.run(vec![2, 5, 7]), Ok(VecDeque::from(vec![0, 1, 7])));
+        let taint = machine.taint().unwrap();
+        assert_eq!(taint.outputs().iter().map(ranges).collect::<Vec<_>>(), vec!["0", "0", "2"]);
+        assert_eq!(ranges(&taint.unused()), "1");
+        assert!(taint.control().is_empty());
+    }
+
+    #[test]
+    fn relative_base() {
+        // in [20]; jz [20], 9; arb 1; arb -1; out [rb+0]. The relative base
+        // depends on the input, even though it is the same after the join.
+        let mut machine = IntcodeProg::new(&[3, 20, 1006, 20, 9, 109, 1, 109, -1, 204, 0, 99]);
+        machine.enable_taint();
+        assert_eq!(machine.run(vec![5]), Ok(VecDeque::from(vec![3])));
+        let taint = machine.taint().unwrap();
+        assert_eq!(taint.outputs().iter().map(ranges).collect::<Vec<_>>(), vec!["0"]);
+        assert!(taint.control().is_empty());
+    }
+}
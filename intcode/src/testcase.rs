@@ -0,0 +1,239 @@
+// Plain-text test cases for the Intcode VM.
+//
+// A test file contains one or more cases. Each case starts with a `case`
+// line and is followed by `key: value` lines:
+//
+//     # Outputs 1 if the input equals 8.
+//     case: equal to 8 (position mode)
+//     program: 3,9,8,9,10,9,4,9,99,-1,8
+//     input: 8
+//     output: 1
+//     memory: 9=1, 10=8
+//     steps: 100
+//
+// `program` and `output` are required, `input`, `memory` (expected values
+// of memory cells after the program halted) and `steps` (step budget) are
+// optional. Repeated `program`, `input`, `output` and `memory` lines are
+// appended, so long programs can be split over several lines. Blank lines
+// and lines starting with `#` are ignored.
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::panic;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::fuzz::panic_message;
+use crate::IntcodeProg;
+use crate::ProgramStatus;
+
+// File extension of test case files.
+pub const EXTENSION: &str = "ict";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    pub name:    String,
+    // Line of the `case` header, for error reports.
+    pub line:    usize,
+    pub program: Vec<i64>,
+    pub input:   Vec<i64>,
+    pub output:  Vec<i64>,
+    pub memory:  Vec<(usize, i64)>,
+    pub steps:   Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub name:   String,
+    // Line of the case header.
+    pub line:   usize,
+    pub result: Result<(), String>,
+}
+
+fn parse_values(text: &str) -> Result<Vec<i64>, String> {
+    text.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.parse().map_err(|_| format!("invalid value {}", s)))
+        .collect()
+}
+
+fn parse_cells(text: &str) -> Result<Vec<(usize, i64)>, String> {
+    text.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let (addr, val) = s
+                .split_once('=')
+                .ok_or_else(|| format!("expected addr=value, got {}", s))?;
+            let addr        = addr.trim().parse().map_err(|_| format!("invalid address {}", addr))?;
+            let val         = val.trim().parse().map_err(|_| format!("invalid value {}", val))?;
+            Ok((addr, val))
+        })
+        .collect()
+}
+
+pub fn parse(text: &str) -> Result<Vec<TestCase>, String> {
+    let mut cases: Vec<TestCase> = Vec::new();
+    let mut has_output           = false;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let err = |msg: String| format!("line {}: {}", i + 1, msg);
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| err(String::from("expected key: value")))?;
+        let (key, value) = (key.trim(), value.trim());
+        if key == "case" {
+            if cases.last().is_some_and(|c| c.program.is_empty() || !has_output) {
+                return Err(err(String::from("previous case lacks a program or output")));
+            }
+            has_output = false;
+            cases.push(TestCase {
+                name:    value.to_string(),
+                line:    i + 1,
+                program: Vec::new(),
+                input:   Vec::new(),
+                output:  Vec::new(),
+                memory:  Vec::new(),
+                steps:   None,
+            });
+            continue;
+        }
+        let case = cases
+            .last_mut()
+            .ok_or_else(|| err(String::from("expected a case line first")))?;
+        match key {
+            "program" => case.program.extend(parse_values(value).map_err(err)?),
+            "input"   => case.input.extend(parse_values(value).map_err(err)?),
+            "output"  => {
+                case.output.extend(parse_values(value).map_err(err)?);
+                has_output = true;
+            }
+            "memory"  => case.memory.extend(parse_cells(value).map_err(err)?),
+            "steps"   => {
+                let steps  = value.parse().map_err(|_| err(format!("invalid step budget {}", value)))?;
+                case.steps = Some(steps);
+            }
+            key       => return Err(err(format!("unknown key {}", key))),
+        }
+    }
+    if cases.last().is_some_and(|c| c.program.is_empty() || !has_output) {
+        return Err(String::from("last case lacks a program or output"));
+    }
+    Ok(cases)
+}
+
+impl TestCase {
+    // Runs the case and describes the first failed expectation. A panic
+    // fails just this case.
+    pub fn run(&self) -> Result<(), String> {
+        panic::catch_unwind(|| self.check())
+            .unwrap_or_else(|payload| Err(format!("panicked: {}", panic_message(payload))))
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let mut prog   = IntcodeProg::new(&self.program);
+        let mut input  = VecDeque::from(self.input.clone());
+        let mut output = VecDeque::new();
+        let mut steps  = 0;
+        loop {
+            if self.steps.is_some_and(|max| steps >= max) {
+                return Err(format!("step budget of {} exceeded", steps));
+            }
+            steps += 1;
+            match prog.try_exec_instr(&mut input, &mut output).map_err(|e| e.to_string())? {
+                ProgramStatus::Success         => (),
+                ProgramStatus::Finished        => break,
+                ProgramStatus::WaitingForInput => {
+                    return Err(String::from("program is waiting for more input"))
+                }
+            }
+        }
+        if output != self.output {
+            return Err(format!("expected output {:?}, got {:?}", self.output, output));
+        }
+        for &(addr, expected) in self.memory.iter() {
+            let val = prog.mem().get(addr).unwrap_or(0);
+            if val != expected {
+                return Err(format!("expected [{}] = {}, got {}", addr, expected, val));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn run_file<P: AsRef<Path>>(path: P) -> Result<Vec<Outcome>, Box<dyn Error>> {
+    let cases = parse(&fs::read_to_string(path)?)?;
+    Ok(cases
+        .iter()
+        .map(|case| Outcome { name: case.name.clone(), line: case.line, result: case.run() })
+        .collect())
+}
+
+// All test files in a directory (not recursive), sorted by name. A path to
+// a single file is returned as is.
+pub fn discover<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let path = path.as_ref();
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+        if file.extension().is_some_and(|e| e == EXTENSION) {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_run() {
+        let text = "\
+            # Doubles its input.\n\
+            case: double\n\
+            program: 3,9,1002,9,2,9,4,9,\n\
+            program: 99,0\n\
+            input: 21\n\
+            output: 42\n\
+            memory: 9=42, 0=3\n\
+            \n\
+            case: wrong output\n\
+            program: 104,1,99\n\
+            output: 2\n\
+            \n\
+            case: endless\n\
+            program: 1105,1,0\n\
+            output:\n\
+            steps: 50\n";
+        let cases = parse(text).unwrap();
+        assert_eq!(cases.len(), 3);
+        assert_eq!(cases[0].program.len(), 10);
+        assert_eq!(cases[0].memory, vec![(9, 42), (0, 3)]);
+        assert_eq!(cases[1].line, 9);
+        assert_eq!(cases[0].run(), Ok(()));
+        assert_eq!(cases[1].run(), Err(String::from("expected output [2], got [1]")));
+        assert_eq!(cases[2].run(), Err(String::from("step budget of 50 exceeded")));
+
+        // A truncated program fails, the runner goes on.
+        let cases = parse("case: cut off\nprogram: 1\noutput:").unwrap();
+        assert_eq!(cases[0].run(), Err(String::from("Invalid op code 0 at position 4!")));
+
+        let cases = parse(" case : spaced\nprogram: 99\noutput:").unwrap();
+        assert_eq!((cases[0].name.as_str(), cases[0].line), ("spaced", 1));
+
+        assert!(parse("program: 99").is_err());
+        assert!(parse("case: a\nprogram: 99").is_err());
+        assert!(parse("case: a\nprogram: 99\noutput:\nfoo: 1").is_err());
+        assert_eq!(parse("case: a\nmemory: 1").unwrap_err(), "line 2: expected addr=value, got 1");
+    }
+}
@@ -0,0 +1,430 @@
+// Compact binary execution traces.
+//
+// A trace starts with the header `ICTRACE` and a version byte, followed by
+// one record per executed instruction:
+//
+//     ip        varint
+//     instr     zigzag varint (the instruction word)
+//     operands  varint count, then one zigzag varint per raw parameter
+//     write     varint 0 if nothing was written, otherwise addr + 1
+//               followed by the zigzag varint value
+//     rel_base  zigzag varint, relative to the previous record
+//
+// Most instructions take five to ten bytes. `diff` compares two traces and
+// reports the first step where they differ, along with the calls active at
+// that point (see the callstack module).
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+use crate::callstack::CallStack;
+use crate::disasm;
+use crate::disasm::Op;
+use crate::symbols::SymbolMap;
+use crate::IntcodeError;
+use crate::IntcodeProg;
+use crate::ProgramStatus;
+
+const MAGIC: &[u8; 8] = b"ICTRACE\x01";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub ip:       usize,
+    pub instr:    i64,
+    pub operands: Vec<i64>,
+    pub write:    Option<(usize, i64)>,
+    // Relative base after the instruction was executed.
+    pub rel_base: i64,
+}
+
+impl Step {
+    // Disassembly of the executed instruction, with symbol names if given.
+    pub fn describe(&self, symbols: &SymbolMap) -> String {
+        let mut mem = vec![self.instr];
+        mem.extend_from_slice(&self.operands);
+        let text = match disasm::decode(&mem, 0) {
+            Some(instr) => instr.symbolized(symbols),
+            None        => format!("op {}", self.instr),
+        };
+        let mut out = format!("{:>6}: {:<28} rb={}", self.ip, text, self.rel_base);
+        if let Some((addr, val)) = self.write {
+            match symbols.name(addr) {
+                Some(name) => out += &format!(" [{}]={}", name, val),
+                None       => out += &format!(" [{}]={}", addr, val),
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.describe(&SymbolMap::default()))
+    }
+}
+
+// Executes a single instruction and describes it. No step is returned if
+// the machine is waiting for input, since nothing was executed.
+pub fn exec_traced(
+    prog:   &mut IntcodeProg,
+    input:  &mut VecDeque<i64>,
+    output: &mut VecDeque<i64>,
+) -> Result<(ProgramStatus, Option<Step>), IntcodeError> {
+    let ip      = prog.ip();
+    let instr   = prog.mem().get(ip).unwrap_or(0);
+    let op_code = instr % 100;
+    let arity   = match prog.extensions.get(&op_code) {
+        Some(ext) => ext.arity,
+        None      => Op::from_code(op_code).map_or(0, |op| op.arity()),
+    };
+    let operands = prog.dump_range(ip + 1..ip + 1 + arity);
+    let status   = prog.try_exec_instr(input, output)?;
+    if status == ProgramStatus::WaitingForInput {
+        return Ok((status, None));
+    }
+    let step = Step { ip, instr, operands, write: prog.last_write(), rel_base: prog.rel_base() };
+    Ok((status, Some(step)))
+}
+
+pub(crate) fn write_varint<Wr: Write>(out: &mut Wr, mut val: u64) -> io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (val & 0x7f) as u8;
+        val    >>= 7;
+        if val == 0 {
+            buf[len] = byte;
+            len     += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len     += 1;
+    }
+    out.write_all(&buf[..len])
+}
+
+pub(crate) fn write_signed<Wr: Write>(out: &mut Wr, val: i64) -> io::Result<()> {
+    write_varint(out, ((val << 1) ^ (val >> 63)) as u64)
+}
+
+// Returns None at a clean end of the input.
+fn read_varint<R: Read>(input: &mut R) -> io::Result<Option<u64>> {
+    let mut val   = 0u64;
+    let mut shift = 0;
+    let mut byte  = [0u8; 1];
+    loop {
+        if input.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        if shift >= 64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "varint too long"));
+        }
+        val |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(val));
+        }
+        shift += 7;
+    }
+}
+
+pub(crate) fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    read_varint(input)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into())
+}
+
+pub(crate) fn read_signed<R: Read>(input: &mut R) -> io::Result<i64> {
+    let val = read_u64(input)?;
+    Ok((val >> 1) as i64 ^ -((val & 1) as i64))
+}
+
+pub struct TraceWriter<Wr: Write> {
+    out:      Wr,
+    rel_base: i64,
+}
+
+impl<Wr: Write> TraceWriter<Wr> {
+    pub fn new(mut out: Wr) -> io::Result<TraceWriter<Wr>> {
+        out.write_all(MAGIC)?;
+        Ok(TraceWriter { out, rel_base: 0 })
+    }
+
+    pub fn write_step(&mut self, step: &Step) -> io::Result<()> {
+        write_varint(&mut self.out, step.ip as u64)?;
+        write_signed(&mut self.out, step.instr)?;
+        write_varint(&mut self.out, step.operands.len() as u64)?;
+        for &val in step.operands.iter() {
+            write_signed(&mut self.out, val)?;
+        }
+        match step.write {
+            Some((addr, val)) => {
+                write_varint(&mut self.out, addr as u64 + 1)?;
+                write_signed(&mut self.out, val)?;
+            }
+            None => write_varint(&mut self.out, 0)?,
+        }
+        write_signed(&mut self.out, step.rel_base.wrapping_sub(self.rel_base))?;
+        self.rel_base = step.rel_base;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+pub struct TraceReader<R: Read> {
+    input:    R,
+    rel_base: i64,
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<TraceReader<R>> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an Intcode trace"));
+        }
+        Ok(TraceReader { input, rel_base: 0 })
+    }
+
+    pub fn read_step(&mut self) -> io::Result<Option<Step>> {
+        let ip = match read_varint(&mut self.input)? {
+            Some(ip) => ip as usize,
+            None     => return Ok(None),
+        };
+        let instr    = read_signed(&mut self.input)?;
+        let count    = read_u64(&mut self.input)?;
+        let operands = (0..count)
+            .map(|_| read_signed(&mut self.input))
+            .collect::<io::Result<_>>()?;
+        let write    = match read_u64(&mut self.input)? {
+            0    => None,
+            addr => Some((addr as usize - 1, read_signed(&mut self.input)?)),
+        };
+        self.rel_base = self.rel_base.wrapping_add(read_signed(&mut self.input)?);
+        Ok(Some(Step { ip, instr, operands, write, rel_base: self.rel_base }))
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Step>;
+
+    fn next(&mut self) -> Option<io::Result<Step>> {
+        self.read_step().transpose()
+    }
+}
+
+// Runs a program to completion (or until `max_steps` instructions were
+// executed) and writes its trace.
+pub fn record<Wr: Write>(
+    prog:      &mut IntcodeProg,
+    input:     Vec<i64>,
+    out:       Wr,
+    max_steps: Option<u64>,
+) -> Result<VecDeque<i64>, Box<dyn std::error::Error>> {
+    let mut writer = TraceWriter::new(out)?;
+    let mut input  = VecDeque::from(input);
+    let mut output = VecDeque::new();
+    let mut steps  = 0;
+    while max_steps.is_none_or(|max| steps < max) {
+        let (status, step) = exec_traced(prog, &mut input, &mut output)?;
+        if let Some(step) = step {
+            writer.write_step(&step)?;
+        }
+        steps += 1;
+        match status {
+            ProgramStatus::Success         => (),
+            ProgramStatus::Finished        => break,
+            ProgramStatus::WaitingForInput => {
+                return Err(IntcodeError::MissingInput { ip: prog.ip() }.into())
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(output)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // Index of the first differing step.
+    pub step:   u64,
+    // Common steps right before the divergence.
+    pub before: Vec<Step>,
+    // Steps of either trace from the divergence on (empty if it ended).
+    pub left:   Vec<Step>,
+    pub right:  Vec<Step>,
+    // Calls active at the divergence, following the left trace.
+    pub calls:  CallStack,
+}
+
+impl Divergence {
+    pub fn report(&self, symbols: &SymbolMap) -> String {
+        let mut out   = format!("traces diverge at step {}\n", self.step);
+        let first     = self.step - self.before.len() as u64;
+        let ip        = self.left.first().or_else(|| self.before.last()).map(|s| s.ip);
+        if let Some(ip) = ip.filter(|_| self.calls.depth() > 0) {
+            let trace         = self.calls.backtrace(ip, symbols);
+            let names: Vec<_> = trace.iter().map(|(ip, name)| format!("{}@{}", name, ip)).collect();
+            out += &format!("call stack: {}\n", names.join(" "));
+        }
+        let mut lines = |prefix: &str, first: u64, steps: &[Step]| {
+            if steps.is_empty() {
+                out += &format!("{} {:>8}  (end of trace)\n", prefix, first);
+            }
+            for (i, step) in steps.iter().enumerate() {
+                out += &format!("{} {:>8}  {}\n", prefix, first + i as u64, step.describe(symbols));
+            }
+        };
+        if !self.before.is_empty() {
+            lines(" ", first, &self.before);
+        }
+        lines("<", self.step, &self.left);
+        lines(">", self.step, &self.right);
+        out
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.report(&SymbolMap::default()))
+    }
+}
+
+// Finds the first step where the traces differ, with up to `context` steps
+// before and after it. Returns None if the traces are identical.
+pub fn diff<A: Read, B: Read>(
+    left:    A,
+    right:   B,
+    context: usize,
+) -> io::Result<Option<Divergence>> {
+    let mut left   = TraceReader::new(left)?;
+    let mut right  = TraceReader::new(right)?;
+    let mut before = VecDeque::new();
+    let mut step   = 0;
+    let mut calls  = CallStack::new();
+    let mut last   = None;
+    loop {
+        let (a, b) = (left.read_step()?, right.read_step()?);
+        if let (Some(prev), Some(next)) = (&last, a.as_ref().or(b.as_ref())) {
+            observe(&mut calls, prev, next.ip);
+        }
+        if a == b {
+            match a {
+                Some(s) => {
+                    last = Some(s.clone());
+                    before.push_back(s);
+                }
+                None => return Ok(None),
+            }
+            if before.len() > context {
+                before.pop_front();
+            }
+            step += 1;
+            continue;
+        }
+        let rest = |first: Option<Step>, reader: &mut dyn Iterator<Item = io::Result<Step>>| {
+            first
+                .into_iter()
+                .map(Ok)
+                .chain(reader.take(context))
+                .collect::<io::Result<Vec<_>>>()
+        };
+        return Ok(Some(Divergence {
+            step,
+            before: before.into_iter().collect(),
+            left:   rest(a, &mut left)?,
+            right:  rest(b, &mut right)?,
+            calls,
+        }));
+    }
+}
+
+fn observe(calls: &mut CallStack, step: &Step, next_ip: usize) {
+    let write = step.write.map(|(addr, val)| (addr, val as i128));
+    calls.observe(step.ip, step.instr % 100, write, step.rel_base, next_ip);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs the sum of all inputs up to the first zero.
+    const SUM: [i64; 15] = [3, 15, 1006, 15, 12, 1, 16, 15, 16, 1105, 1, 0, 4, 16, 99];
+
+    fn trace(input: Vec<i64>) -> Vec<u8> {
+        let mut prog = SUM.to_vec();
+        prog.extend_from_slice(&[0, 0]);
+        let mut buf = Vec::new();
+        record(&mut IntcodeProg::new(&prog), input, &mut buf, Some(1000)).unwrap();
+        buf
+    }
+
+    #[test]
+    fn encoding() {
+        let steps = vec![
+            Step { ip: 0, instr: 109, operands: vec![-300], write: None, rel_base: -300 },
+            Step {
+                ip:       70000,
+                instr:    21101,
+                operands: vec![i64::MIN, i64::MAX, 3],
+                write:    Some((0, -1)),
+                rel_base: 5,
+            },
+        ];
+        let mut buf    = Vec::new();
+        let mut writer = TraceWriter::new(&mut buf).unwrap();
+        for step in steps.iter() {
+            writer.write_step(step).unwrap();
+        }
+        let read: Vec<_> = TraceReader::new(&buf[..]).unwrap().collect::<io::Result<_>>().unwrap();
+        assert_eq!(read, steps);
+        assert!(TraceReader::new(&buf[..buf.len() - 1]).unwrap().nth(1).unwrap().is_err());
+        assert!(TraceReader::new(&b"ICTRACE"[..]).is_err());
+    }
+
+    #[test]
+    fn divergence() {
+        let a = trace(vec![1, 2, 0]);
+        assert!(a.len() < 8 + 15 * 8);
+        assert_eq!(diff(&a[..], &a[..], 3).unwrap(), None);
+
+        // Same up to the second input.
+        let b   = trace(vec![1, 3, 0]);
+        let div = diff(&a[..], &b[..], 2).unwrap().unwrap();
+        assert_eq!(div.step, 4);
+        assert_eq!(div.before.len(), 2);
+        assert_eq!(div.left[0].write, Some((15, 2)));
+        assert_eq!(div.right[0].write, Some((15, 3)));
+        assert_eq!(div.left.len(), 3);
+        assert!(div.to_string().starts_with("traces diverge at step 4\n "));
+
+        // One trace ends early.
+        let c   = trace(vec![1, 0]);
+        let div = diff(&a[..], &c[..], 1).unwrap().unwrap();
+        assert_eq!(div.step, 4);
+        assert_eq!(div.right[0].write, Some((15, 0)));
+    }
+
+    #[test]
+    fn call_stack() {
+        let prog = crate::compiler::compile(
+            "fn read() { var x = input(); output(x); }
+             fn main() { read(); }",
+        )
+        .unwrap();
+        let trace = |input: Vec<i64>| {
+            let mut buf = Vec::new();
+            record(&mut IntcodeProg::new(&prog), input, &mut buf, None).unwrap();
+            buf
+        };
+        let div     = diff(&trace(vec![1])[..], &trace(vec![2])[..], 0).unwrap().unwrap();
+        let symbols = SymbolMap::parse("10 read code").unwrap();
+        assert_eq!(div.calls.depth(), 2);
+        assert!(div.report(&symbols).contains("\ncall stack: read@16 f37@47 main@6\n"));
+    }
+}
@@ -0,0 +1,114 @@
+// Word types the Intcode VM can operate on, and how arithmetic overflow of
+// these words is handled.
+
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::str::FromStr;
+
+pub trait Word:
+    Copy + Debug + Display + Default + Ord + Hash + FromStr + Send + Sync + 'static
+{
+    const ZERO: Self;
+    const ONE: Self;
+
+    fn to_i128(self) -> i128;
+    fn from_i128(val: i128) -> Option<Self>;
+
+    fn checked_add(self, other: Self) -> Option<Self>;
+    fn wrapping_add(self, other: Self) -> Self;
+    fn saturating_add(self, other: Self) -> Self;
+    fn checked_mul(self, other: Self) -> Option<Self>;
+    fn wrapping_mul(self, other: Self) -> Self;
+    fn saturating_mul(self, other: Self) -> Self;
+}
+
+macro_rules! impl_word {
+    ($($t:ty),*) => {$(
+        impl Word for $t {
+            const ZERO: Self = 0;
+            const ONE:  Self = 1;
+
+            fn to_i128(self) -> i128 {
+                self as i128
+            }
+
+            fn from_i128(val: i128) -> Option<Self> {
+                use std::convert::TryFrom;
+                <$t>::try_from(val).ok()
+            }
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$t>::checked_add(self, other)
+            }
+
+            fn wrapping_add(self, other: Self) -> Self {
+                <$t>::wrapping_add(self, other)
+            }
+
+            fn saturating_add(self, other: Self) -> Self {
+                <$t>::saturating_add(self, other)
+            }
+
+            fn checked_mul(self, other: Self) -> Option<Self> {
+                <$t>::checked_mul(self, other)
+            }
+
+            fn wrapping_mul(self, other: Self) -> Self {
+                <$t>::wrapping_mul(self, other)
+            }
+
+            fn saturating_mul(self, other: Self) -> Self {
+                <$t>::saturating_mul(self, other)
+            }
+        }
+    )*};
+}
+
+impl_word!(i32, i64, i128);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    // Overflowing arithmetic is reported as an error.
+    #[default]
+    Error,
+    // Two's complement wrap-around (what release builds used to do).
+    Wrap,
+    // Results are clamped to the smallest/largest representable word.
+    Saturate,
+}
+
+impl Overflow {
+    // Returns None if the operation overflowed under the error policy.
+    pub fn add<W: Word>(self, a: W, b: W) -> Option<W> {
+        match self {
+            Overflow::Error    => a.checked_add(b),
+            Overflow::Wrap     => Some(a.wrapping_add(b)),
+            Overflow::Saturate => Some(a.saturating_add(b)),
+        }
+    }
+
+    pub fn mul<W: Word>(self, a: W, b: W) -> Option<W> {
+        match self {
+            Overflow::Error    => a.checked_mul(b),
+            Overflow::Wrap     => Some(a.wrapping_mul(b)),
+            Overflow::Saturate => Some(a.saturating_mul(b)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow_policies() {
+        assert_eq!(Overflow::Error.add(i64::MAX, 1), None);
+        assert_eq!(Overflow::Wrap.add(i64::MAX, 1), Some(i64::MIN));
+        assert_eq!(Overflow::Saturate.add(i64::MAX, 1), Some(i64::MAX));
+        assert_eq!(Overflow::Error.mul(i32::MIN, 2), None);
+        assert_eq!(Overflow::Wrap.mul(i32::MIN, 2), Some(0));
+        assert_eq!(Overflow::Saturate.mul(i32::MIN, 2), Some(i32::MIN));
+        assert_eq!(Overflow::Error.mul(i64::MAX as i128, 4), Some(i64::MAX as i128 * 4));
+    }
+}
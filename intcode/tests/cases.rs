@@ -0,0 +1,23 @@
+// Runs all test case files in tests/cases, see the testcase module for the
+// file format.
+
+use intcode::testcase;
+
+#[test]
+fn case_files() {
+    let dir   = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cases");
+    let files = testcase::discover(dir).unwrap();
+    assert!(!files.is_empty());
+
+    let mut failures = Vec::new();
+    for file in files.iter() {
+        let outcomes = testcase::run_file(file)
+            .unwrap_or_else(|e| panic!("{}: {}", file.display(), e));
+        for outcome in outcomes {
+            if let Err(msg) = outcome.result {
+                failures.push(format!("{}:{}: {}: {}", file.display(), outcome.line, outcome.name, msg));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "failed cases:\n{}", failures.join("\n"));
+}